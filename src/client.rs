@@ -1,40 +1,471 @@
-use reqwest::Client;
+use reqwest::cookie::{CookieStore, Jar};
+use reqwest::{Certificate, Client, Identity, NoProxy, Proxy, RequestBuilder, Response, StatusCode, Url};
 use anyhow::{Result, Context, anyhow};
-use tracing::{error, debug};
-use std::time::Duration;
-use crate::parser::{parse_ont_metrics, OntMetrics};
+use tracing::{error, debug, warn};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::config::{Config, LoginMode, OpticalFormat, PasswordEncoding, TokenPlacement};
+use crate::metrics::{
+    reset_target, CLIENT_PROTOCOL_INFO, CLIENT_REQUESTS, CLIENT_RESPONSE_BYTES, LOGIN_ATTEMPTS, LOGIN_TOKEN_LENGTH, LOGIN_FAILURES, PAGE_AVAILABLE,
+};
+use crate::parser::{parse_diag_optic, parse_ont_metrics, parse_ont_metrics_json, parse_uptime_page, ClientRssi, OntMetrics, WanConnection, WifiSsid};
 use base64::prelude::*;
+use serde::Serialize;
+
+/// Returned when the ONT answers the login POST with its login page again,
+/// i.e. the username or password was refused.
+#[derive(Debug)]
+pub struct CredentialsRejected;
+
+impl std::fmt::Display for CredentialsRejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Login failed: received login page")
+    }
+}
+
+impl std::error::Error for CredentialsRejected {}
+
+/// Returned when an optional page comes back as the login page mid-scrape
+#[derive(Debug)]
+pub struct SessionExpired;
+
+impl std::fmt::Display for SessionExpired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ONT session expired: received login page")
+    }
+}
+
+impl std::error::Error for SessionExpired {}
+
+/// Returned when the ONT answers a request with a non-success status
+#[derive(Debug)]
+pub struct UnexpectedStatus {
+    what: &'static str,
+    status: StatusCode,
+}
+
+impl std::fmt::Display for UnexpectedStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} failed with status: {}", self.what, self.status)
+    }
+}
+
+impl std::error::Error for UnexpectedStatus {}
+
+pub fn is_auth_error(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.is::<CredentialsRejected>())
+}
+
+fn is_session_expired(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.is::<SessionExpired>())
+}
+
+// Logged-out pages are a script redirect to the login, e.g. top.location.replace('/login.asp')
+fn is_login_page(html: &str) -> bool {
+    use regex::Regex;
+
+    Regex::new(r#"location(?:\.replace\(|\.href\s*=|\s*=)\s*['"][^'"]*login\.asp"#)
+        .unwrap()
+        .is_match(html)
+}
+
+// True when the device couldn't be reached at all (refused, unroutable, timed out)
+pub fn is_connect_error(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|re| re.is_connect() || re.is_timeout())
+    })
+}
+
+/// Failure reason for huawei_ont_scrape_errors_total: auth, http, network or parse
+pub fn error_reason(e: &anyhow::Error) -> &'static str {
+    if is_auth_error(e) {
+        "auth"
+    } else if e.chain().any(|cause| cause.is::<UnexpectedStatus>()) {
+        "http"
+    } else if e.chain().any(|cause| {
+        cause.is::<reqwest::Error>() || cause.is::<tokio::time::error::Elapsed>()
+    }) {
+        "network"
+    } else {
+        "parse"
+    }
+}
+
+// Candidate paths for the optional pages, tried in order
+const DEVICE_INFO_PATHS: &[&str] = &[
+    "/html/ssmp/deviceinfo/deviceinfo.asp",
+    "/html/amp/deviceinfo/deviceinfo.asp",
+    "/html/amp/basic/deviceinfo.asp",
+    "/html/advance/deviceinfo/deviceinfo.asp",
+];
+
+// JSON or script uptime, for models without it in stDeviceInfo
+const UPTIME_INFO_PATHS: &[&str] = &[
+    "/html/ssmp/deviceinfo/time.asp",
+    "/html/amp/deviceinfo/time.asp",
+    "/html/ssmp/common/time.asp",
+];
+
+const WAN_INFO_PATHS: &[&str] = &[
+    "/html/bbsp/waninfo/waninfo.asp",
+    "/html/amp/internet/internet.asp",
+    "/html/amp/wan/wan.asp",
+    "/html/advance/internet/internet.asp",
+];
+
+const WLAN_INFO_PATHS: &[&str] = &[
+    "/html/amp/wlanbasic/WlanBasic.asp",
+    "/html/amp/wlan/wlanbasic.asp",
+    "/html/advance/wlan/wlanbasic.asp",
+];
+
+const LAN_INFO_PATHS: &[&str] = &[
+    "/html/bbsp/common/GetLanUserDevInfo.asp",
+    "/html/amp/lanuser/lanuser.asp",
+    "/html/amp/user/user.asp",
+    "/html/advance/user/user.asp",
+];
+
+const PAGE_PATHS: &[(&str, &[&str])] = &[
+    ("device", DEVICE_INFO_PATHS),
+    ("uptime", UPTIME_INFO_PATHS),
+    ("wan", WAN_INFO_PATHS),
+    ("lan", LAN_INFO_PATHS),
+    ("wlan", WLAN_INFO_PATHS),
+];
+
+/// Remembers which candidate path last worked for each optional page. Clients are
+/// recreated on every scrape, so the cache is owned by the caller and shared.
+#[derive(Clone, Default)]
+pub struct PathCache(Arc<Mutex<PathState>>);
+
+#[derive(Default)]
+struct PathState {
+    found: HashMap<&'static str, &'static str>,
+    // What each candidate returned in the last lookup of a page, for /paths
+    tried: HashMap<&'static str, Vec<PathAttempt>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PathAttempt {
+    pub path: &'static str,
+    pub outcome: String,
+}
+
+/// One optional page in the /paths report
+#[derive(Debug, Serialize)]
+pub struct PageReport {
+    pub page: &'static str,
+    // The candidate that last served the page, if any did
+    pub path: Option<&'static str>,
+    pub candidates: &'static [&'static str],
+    // Empty until the page has been looked up
+    pub tried: Vec<PathAttempt>,
+}
+
+impl PathCache {
+    fn get(&self, page: &str) -> Option<&'static str> {
+        self.0.lock().unwrap().found.get(page).copied()
+    }
+
+    fn set(&self, page: &'static str, path: &'static str) {
+        let previous = self.0.lock().unwrap().found.insert(page, path);
+        if previous != Some(path) {
+            debug!("Using {} for {} page", path, page);
+        }
+    }
+
+    fn record_attempts(&self, page: &'static str, attempts: Vec<PathAttempt>) {
+        self.0.lock().unwrap().tried.insert(page, attempts);
+    }
+
+    /// One-line overview of which path serves each optional page, e.g. for bug reports
+    pub fn summary(&self) -> String {
+        let state = self.0.lock().unwrap();
+        PAGE_PATHS
+            .iter()
+            .map(|(page, _)| format!("{}={}", page, state.found.get(page).copied().unwrap_or("none")))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// Working path and last lookup of every optional page
+    pub fn report(&self) -> Vec<PageReport> {
+        let state = self.0.lock().unwrap();
+        PAGE_PATHS
+            .iter()
+            .map(|&(page, candidates)| PageReport {
+                page,
+                path: state.found.get(page).copied(),
+                candidates,
+                tried: state.tried.get(page).cloned().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+/// Cookie jar shared by all clients when COOKIE_STORE_PATH is set, so the ONT
+/// session is kept across scrapes and, through the file, across restarts.
+#[derive(Clone)]
+pub struct SessionStore {
+    jar: Arc<Jar>,
+    path: String,
+}
+
+impl SessionStore {
+    // File format: one `<url> <cookie header>` line per ONT URL
+    pub fn load(path: &str, urls: impl Iterator<Item = impl AsRef<str>>) -> Self {
+        let jar = Arc::new(Jar::default());
+        let urls: Vec<String> = urls.map(|u| u.as_ref().trim_end_matches('/').to_string()).collect();
+
+        match std::fs::read_to_string(path) {
+            Ok(contents) => {
+                for line in contents.lines() {
+                    let Some((url, cookies)) = line.split_once(' ') else { continue };
+                    // Sessions for URLs that are no longer configured are dropped
+                    if !urls.iter().any(|u| u == url) {
+                        continue;
+                    }
+                    let Ok(parsed) = url.parse::<Url>() else { continue };
+                    for cookie in cookies.split("; ") {
+                        jar.add_cookie_str(&format!("{}; Path=/", cookie), &parsed);
+                    }
+                }
+                debug!("Loaded ONT session cookies from {}", path);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => error!("Failed to read cookie store {}: {}", path, e),
+        }
+
+        Self { jar, path: path.to_string() }
+    }
+
+    pub fn save(&self, urls: impl Iterator<Item = impl AsRef<str>>) -> Result<()> {
+        let mut contents = String::new();
+        for url in urls {
+            let url = url.as_ref().trim_end_matches('/');
+            let parsed: Url = url.parse().with_context(|| format!("Invalid ONT URL {}", url))?;
+            if let Some(cookies) = self.jar.cookies(&parsed).and_then(|v| v.to_str().ok().map(str::to_string)) {
+                contents.push_str(&format!("{} {}\n", url, cookies));
+            }
+        }
+        std::fs::write(&self.path, contents)
+            .with_context(|| format!("Failed to write cookie store {}", self.path))
+    }
+}
+
+// The optical page as fetched, before parsing
+struct OpticalPage {
+    body: String,
+    is_json: bool,
+}
 
 pub struct OntClient {
     client: Client,
+    // Kept so the login token can be placed in a cookie next to the session cookies
+    cookies: Arc<Jar>,
+    // Persistent session: validate and reuse it instead of logging in and out every scrape
+    keep_session: bool,
     base_url: String,
-    user: String,
-    pass: String,
+    config: Config,
+    path_cache: PathCache,
 }
 
 impl OntClient {
-    pub fn new(url: &str, user: &str, pass: &str) -> Result<Self> {
-        let client = Client::builder()
-            .cookie_store(true)
-            .timeout(Duration::from_secs(10))
-            .build()?;
+    pub fn new(config: &Config, url: &str, path_cache: PathCache, session: Option<&SessionStore>) -> Result<Self> {
+        let cookies = session.map_or_else(|| Arc::new(Jar::default()), |s| s.jar.clone());
+        let mut builder = Client::builder()
+            .cookie_provider(cookies.clone())
+            .timeout(Duration::from_secs(10));
+
+        if !config.follow_redirects {
+            builder = builder.redirect(reqwest::redirect::Policy::none());
+        }
+
+        match (&config.client_cert, &config.client_key) {
+            (Some(cert), Some(key)) => builder = builder.identity(load_identity(cert, key)?),
+            (None, None) => {}
+            _ => return Err(anyhow!("ONT_CLIENT_CERT and ONT_CLIENT_KEY must be set together")),
+        }
+
+        // Trusted in addition to the built-in roots (add_root_certificate is deprecated)
+        if let Some(bundle) = &config.ca_bundle {
+            builder = builder.tls_certs_merge(load_ca_bundle(bundle)?);
+        }
+
+        // Without ONT_PROXY reqwest already honors HTTP_PROXY/HTTPS_PROXY/ALL_PROXY/NO_PROXY
+        if let Some(proxy_url) = &config.proxy {
+            let proxy = Proxy::all(proxy_url)
+                .with_context(|| format!("Invalid ONT_PROXY {}", proxy_url))?
+                .no_proxy(NoProxy::from_env());
+            builder = builder.proxy(proxy);
+        }
+
+        let client = builder.build()?;
 
         Ok(Self {
             client,
+            cookies,
+            keep_session: session.is_some(),
             base_url: url.trim_end_matches('/').to_string(),
-            user: user.to_string(),
-            pass: pass.to_string(),
+            config: config.clone(),
+            path_cache,
         })
     }
 
     pub async fn scrape_metrics(&self) -> Result<OntMetrics> {
-        self.login().await.context("Failed to login")?;
+        // A reused session was checked on the optical page, which is parsed as it is
+        let prefetched = match self.config.login_mode {
+            LoginMode::Cgi => self.ensure_login().await.context("Failed to login")?,
+            LoginMode::Basic => None,
+        };
         
-        // Scrape optical metrics (primary)
-        let mut result = self.fetch_optical_info().await
-            .context("Failed to fetch optical info")?;
+        // Scrape optical metrics (primary), from the diagnostic command if the page fails
+        let mut result = match self.fetch_optical_info(prefetched).await {
+            Ok(result) => result,
+            Err(e) if self.config.enable_diag && !is_auth_error(&e) => {
+                warn!("Optical page failed, trying the diagnostic command: {:#}", e);
+                self.fetch_diag_optical_info()
+                    .await
+                    .with_context(|| format!("Failed to fetch optical info ({:#}), and from the diagnostic command", e))?
+            }
+            Err(e) => return Err(e.context("Failed to fetch optical info")),
+        };
         
         // Try to fetch additional metrics (optional - don't fail if unavailable)
+        let sections = [
+            ("device", self.config.enable_device),
+            ("wan", self.config.enable_wan),
+            ("lan", self.config.enable_lan),
+            ("wlan", self.config.enable_wlan),
+        ];
+        let mut relogged = false;
+        for (section, enabled) in sections {
+            if !enabled {
+                continue;
+            }
+            let ok = match self.collect_section(section, &mut result).await {
+                Ok(ok) => ok,
+                // The session can run out mid-scrape; log in again once and retry the section
+                Err(e) if !relogged && self.config.login_mode == LoginMode::Cgi => {
+                    warn!("{} section: {:#}, logging in again", section, e);
+                    relogged = true;
+                    match self.login().await {
+                        Ok(()) => self.collect_section(section, &mut result).await.unwrap_or(false),
+                        Err(e) => {
+                            warn!("Re-login failed: {:#}", e);
+                            false
+                        }
+                    }
+                }
+                Err(e) => {
+                    debug!("{} section: {:#}", section, e);
+                    false
+                }
+            };
+            result.sections.insert(section, ok);
+        }
+        
+        if self.config.login_mode == LoginMode::Cgi && !self.keep_session {
+            let logout_res = self.logout().await;
+            if let Err(e) = logout_res {
+                error!("Logout failed: {}", e);
+            }
+        }
+
+        Ok(result)
+    }
+
+    // Log in and straight back out, used to validate credentials at startup
+    pub async fn check_login(&self) -> Result<()> {
+        if self.config.login_mode == LoginMode::Basic {
+            let resp = self.send("landing", self.get(&self.base_url)).await.context("Failed to send request")?;
+            if resp.status() == StatusCode::UNAUTHORIZED {
+                return Err(CredentialsRejected.into());
+            }
+            return Ok(());
+        }
+
+        self.ensure_login().await.context("Failed to login")?;
+        if !self.keep_session
+            && let Err(e) = self.logout().await
+        {
+            error!("Logout failed: {}", e);
+        }
+        Ok(())
+    }
+
+    // The `target` label of every metric this client records: the configured ONT,
+    // also when ONT_URL_FALLBACK is the one being scraped
+    fn target(&self) -> &str {
+        &self.config.ont_url
+    }
+
+    // GET with HTTP Basic credentials attached when running in basic login mode
+    fn get(&self, url: &str) -> RequestBuilder {
+        let req = self.client.get(url);
+        match self.config.login_mode {
+            LoginMode::Basic => req.basic_auth(&self.config.ont_user, Some(&self.config.ont_pass)),
+            LoginMode::Cgi => req,
+        }
+    }
+
+    // Every request to the ONT goes through here so the exporter's footprint is counted
+    async fn send(&self, page: &str, req: RequestBuilder) -> reqwest::Result<Response> {
+        CLIENT_REQUESTS.with_label_values(&[self.target(), page]).inc();
+        req.send().await
+    }
+
+    // Reads the body in chunks so an oversized response is cut off at ONT_MAX_BODY_BYTES
+    // instead of being buffered whole
+    async fn read_text(&self, page: &str, mut resp: Response) -> Result<String> {
+        let content_type = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let limit = self.config.max_body_bytes;
+        let too_large = || anyhow!("{} response exceeds ONT_MAX_BODY_BYTES ({} bytes)", page, limit);
+
+        if resp.content_length().is_some_and(|len| len > limit as u64) {
+            return Err(too_large());
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = resp.chunk().await? {
+            if body.len() + chunk.len() > limit {
+                CLIENT_RESPONSE_BYTES.with_label_values(&[self.target(), page]).inc_by(body.len() as u64);
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        CLIENT_RESPONSE_BYTES.with_label_values(&[self.target(), page]).inc_by(body.len() as u64);
+        Ok(decode_body(&body, content_type.as_deref()))
+    }
+
+    // Err only for SessionExpired, anything else just marks the section as failed
+    async fn collect_section(&self, section: &'static str, result: &mut OntMetrics) -> Result<bool> {
+        match section {
+            "device" => {
+                let ok = self.collect_device_info(result).await?;
+                if result.uptime_seconds.is_none() {
+                    self.collect_uptime_info(result).await;
+                }
+                Ok(ok)
+            }
+            "wan" => self.collect_wan_info(result).await,
+            "lan" => self.collect_lan_info(result).await,
+            "wlan" => self.collect_wlan_info(result).await,
+            _ => unreachable!("unknown section {}", section),
+        }
+    }
+
+    async fn collect_device_info(&self, result: &mut OntMetrics) -> Result<bool> {
         match self.fetch_device_info().await {
             Ok(device_html) => {
                 match parse_device_info_page(&device_html) {
@@ -46,29 +477,62 @@ impl OntClient {
                         result.uptime_seconds = device_metrics.uptime;
                         result.hardware_version = device_metrics.hardware_version;
                         result.mac_address = device_metrics.mac;
+                        result.customization = device_metrics.customization;
+                        result.cpu_usage_percent = device_metrics.cpu_usage;
+                        result.memory_usage_percent = device_metrics.memory_usage;
+                        result.time_synced = device_metrics.time_synced;
+                        result.device_time_seconds = device_metrics.device_time;
+                        return Ok(true);
                     }
                     Err(e) => debug!("Failed to parse device info: {}", e),
                 }
             }
+            Err(e) if is_session_expired(&e) => return Err(e),
             Err(e) => debug!("Failed to fetch device info: {}", e),
         }
-        
+        Ok(false)
+    }
+
+    async fn collect_uptime_info(&self, result: &mut OntMetrics) {
+        match self.fetch_uptime_info().await {
+            Ok(body) => {
+                result.uptime_seconds = parse_uptime_page(&body);
+                debug!("Uptime from the uptime page: {:?}", result.uptime_seconds);
+            }
+            Err(e) => debug!("Failed to fetch uptime info: {}", e),
+        }
+    }
+
+    async fn collect_wan_info(&self, result: &mut OntMetrics) -> Result<bool> {
         match self.fetch_wan_info().await {
             Ok(wan_html) => {
                 match parse_wan_page(&wan_html) {
-                    Ok(wan_metrics) => {
-                        debug!("WAN info parsed successfully");
-                        result.wan_status = wan_metrics.status;
-                        result.wan_ip = wan_metrics.ip;
-                        result.wan_rx_bytes = wan_metrics.rx_bytes;
-                        result.wan_tx_bytes = wan_metrics.tx_bytes;
+                    Ok(connections) => {
+                        debug!("WAN info parsed successfully ({} connections)", connections.len());
+                        // The single-WAN fields follow the Internet connection
+                        let primary = connections
+                            .iter()
+                            .find(|c| c.service.contains("INTERNET"))
+                            .or(connections.first());
+                        if let Some(primary) = primary {
+                            result.wan_status = primary.status.clone();
+                            result.wan_ip = primary.ip.clone();
+                            result.wan_rx_bytes = primary.rx_bytes;
+                            result.wan_tx_bytes = primary.tx_bytes;
+                        }
+                        result.wan_connections = connections;
+                        return Ok(true);
                     }
                     Err(e) => debug!("Failed to parse WAN info: {}", e),
                 }
             }
+            Err(e) if is_session_expired(&e) => return Err(e),
             Err(e) => debug!("Failed to fetch WAN info: {}", e),
         }
-        
+        Ok(false)
+    }
+
+    async fn collect_lan_info(&self, result: &mut OntMetrics) -> Result<bool> {
         match self.fetch_lan_info().await {
             Ok(lan_html) => {
                 match parse_lan_page(&lan_html) {
@@ -77,185 +541,497 @@ impl OntClient {
                         result.lan_clients_count = client_metrics.lan_count;
                         result.wifi_clients_count = client_metrics.wifi_count;
                         result.total_clients_count = client_metrics.total_count;
+                        result.lan_clients_declared = client_metrics.declared_count;
+                        let parsed = client_metrics.total_count.unwrap_or(0);
+                        if let Some(declared) = client_metrics.declared_count
+                            && declared != parsed
+                        {
+                            warn!(
+                                "LAN page declares {} clients but {} were parsed, the response may be truncated",
+                                declared, parsed
+                            );
+                        }
+                        result.client_leases = client_metrics.leases;
+                        result.client_rssi = client_metrics.rssi;
+                        return Ok(true);
                     }
                     Err(e) => debug!("Failed to parse LAN info: {}", e),
                 }
             }
+            Err(e) if is_session_expired(&e) => return Err(e),
             Err(e) => debug!("Failed to fetch LAN info: {}", e),
         }
-        
-        let logout_res = self.logout().await;
-        if let Err(e) = logout_res {
-            error!("Logout failed: {}", e);
-        }
-
-        Ok(result)
+        Ok(false)
     }
 
     async fn get_login_token(&self) -> Result<String> {
         let url = format!("{}/asp/GetRandCount.asp", self.base_url);
         
-        let resp = self.client.post(&url)
+        let req = self.client.post(&url)
             .header("Referer", format!("{}/", self.base_url))
             .header("X-Requested-With", "XMLHttpRequest")
-            .header("Origin", &self.base_url)
-            .send()
+            .header("Origin", &self.base_url);
+        let resp = self.send("token", req)
             .await
             .context("Failed to send GetRandCount request")?;
             
         if !resp.status().is_success() {
-             return Err(anyhow!("GetRandCount failed with status: {}", resp.status()));
+             return Err(UnexpectedStatus { what: "GetRandCount", status: resp.status() }.into());
         }
 
-        let text = resp.text().await.context("Failed to get GetRandCount response text")?;
-        
-        let token = text.trim_start_matches('\u{feff}').trim();
+        let text = self.read_text("token", resp).await.context("Failed to get GetRandCount response text")?;
+        // Only the length, the token itself is a credential
+        LOGIN_TOKEN_LENGTH.with_label_values(&[self.target()]).set(text.trim_start_matches('\u{feff}').trim().len() as i64);
         
-        Ok(token.to_string())
+        validate_login_token(&text)
+    }
+
+    // Reuse a persisted session if the ONT still accepts it, otherwise log in. Returns
+    // the optical page the session was checked on so the scrape doesn't fetch it again.
+    async fn ensure_login(&self) -> Result<Option<OpticalPage>> {
+        if self.keep_session
+            && let Some(page) = self.session_alive().await
+        {
+            debug!("Reusing existing ONT session");
+            return Ok(Some(page));
+        }
+        self.login().await?;
+        Ok(None)
+    }
+
+    // The optical page renders its data (opticInfos array or JSON) only for a logged-in session
+    async fn session_alive(&self) -> Option<OpticalPage> {
+        let page = self.fetch_optical_page("session").await.ok()?;
+        let alive = match self.config.optical_format {
+            OpticalFormat::Asp => page.body.contains("opticInfos") || page.body.contains("stOpticInfo"),
+            OpticalFormat::Json => page.body.contains('{') && !page.body.contains("login.asp"),
+        };
+        alive.then_some(page)
+    }
+
+    fn optical_path(&self) -> &'static str {
+        match self.config.optical_format {
+            OpticalFormat::Asp => "/html/amp/opticinfo/opticinfo.asp",
+            OpticalFormat::Json => "/api/ntwk/wan?type=optic",
+        }
+    }
+
+    // Some firmware renders the page only with a query such as ?RequestFile=..., or
+    // when it is named in a frame parameter (ONT_OPTICAL_QUERY="frame={path}")
+    fn optical_url(&self) -> String {
+        let path = self.optical_path();
+        let Some(query) = &self.config.optical_query else {
+            return format!("{}{}", self.base_url, path);
+        };
+        let page = path.split('?').next().unwrap_or(path).trim_start_matches('/');
+        let separator = if path.contains('?') { '&' } else { '?' };
+        format!("{}{}{}{}", self.base_url, path, separator, query.replace("{path}", page))
     }
 
     async fn login(&self) -> Result<()> {
         debug!("Logging in to {}", self.base_url);
+        LOGIN_ATTEMPTS.with_label_values(&[self.target()]).inc();
         
-        let _ = self.client.get(&self.base_url).send().await;
+        let _ = self.send("landing", self.client.get(&self.base_url)).await;
 
-        let token = self.get_login_token().await.context("Failed to get login token")?;
+        let token = match self.get_login_token().await {
+            Ok(token) => token,
+            Err(e) => {
+                LOGIN_FAILURES.with_label_values(&[self.target(), "token"]).inc();
+                return Err(e.context("Failed to get login token"));
+            }
+        };
         debug!("Got login token: {}", token);
 
-        let password_base64 = BASE64_STANDARD.encode(&self.pass);
+        if let Err(e) = self.submit_login(&token).await {
+            let stage = if e.is::<CredentialsRejected>() { "credentials" } else { "login" };
+            LOGIN_FAILURES.with_label_values(&[self.target(), stage]).inc();
+            return Err(e);
+        }
         
-        let params = [
-            ("UserName", self.user.as_str()),
-            ("PassWord", password_base64.as_str()),
-            ("Language", "english"),
-            ("x.X_HW_Token", token.as_str()),
+        if let Some(path) = &self.config.post_login_path
+            && let Err(e) = self.submit_post_login(path, &token).await
+        {
+            LOGIN_FAILURES.with_label_values(&[self.target(), "post_login"]).inc();
+            return Err(e.context("Post-login step failed"));
+        }
+        
+        debug!("Login successful");
+        Ok(())
+    }
+
+    async fn submit_login(&self, token: &str) -> Result<()> {
+        let password_base64 = encode_password(&self.config.ont_pass, self.config.password_encoding);
+        
+        let fields = &self.config.login_fields;
+        let mut params = vec![
+            (fields.username.as_str(), self.config.ont_user.as_str()),
+            (fields.password.as_str(), password_base64.as_str()),
+            (fields.language.as_str(), "english"),
         ];
         
         let login_url = format!("{}/login.cgi", self.base_url);
-        let resp = self.client.post(&login_url)
+        match self.config.token_placement {
+            TokenPlacement::Form => params.push((fields.token.as_str(), token)),
+            TokenPlacement::Cookie => {
+                // Added to the jar rather than set as a header, which would drop the session cookies
+                let url = login_url.parse().context("Invalid login URL")?;
+                self.cookies.add_cookie_str(&format!("{}=english; Path=/", fields.language), &url);
+                self.cookies.add_cookie_str(&format!("{}={}; Path=/", fields.token, token), &url);
+            }
+        }
+        let req = self.client.post(&login_url)
             .header("Referer", format!("{}/", self.base_url))
-            .form(&params)
-            .send()
+            .form(&params);
+        let resp = self.send("login", req)
             .await
             .context("Failed to send login request")?;
 
+        // Only seen with ONT_FOLLOW_REDIRECTS=false: a redirect back to the login page is a rejection
+        if resp.status().is_redirection() {
+            let location = resp
+                .headers()
+                .get(reqwest::header::LOCATION)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default();
+            debug!("Login redirected ({}) to {:?}", resp.status(), location);
+            if location.contains("login.asp") {
+                return Err(CredentialsRejected.into());
+            }
+            return Ok(());
+        }
         if !resp.status().is_success() {
-             return Err(anyhow!("Login request failed with status: {}", resp.status()));
+             return Err(UnexpectedStatus { what: "Login request", status: resp.status() }.into());
         }
              
-        let text = resp.text().await?;
+        let text = self.read_text("login", resp).await?;
         if text.contains("login.asp") && !text.contains("top.location.replace") {
-             return Err(anyhow!("Login failed: received login page"));
+             return Err(CredentialsRejected.into());
+        }
+        
+        Ok(())
+    }
+
+    // ONT_POST_LOGIN_PATH: the confirm step some ISP firmware needs before the session is valid
+    async fn submit_post_login(&self, path: &str, token: &str) -> Result<()> {
+        let params: Vec<(&str, String)> = self
+            .config
+            .post_login_form
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.replace("{token}", token)))
+            .collect();
+        
+        let req = self.client.post(format!("{}{}", self.base_url, path))
+            .header("Referer", format!("{}/", self.base_url))
+            .form(&params);
+        let resp = self.send("post_login", req)
+            .await
+            .context("Failed to send post-login request")?;
+        if !resp.status().is_success() && !resp.status().is_redirection() {
+            return Err(UnexpectedStatus { what: "Post-login request", status: resp.status() }.into());
+        }
+        
+        let text = self.read_text("post_login", resp).await?;
+        if is_login_page(&text) {
+            return Err(CredentialsRejected.into());
         }
         
-        debug!("Login successful");
         Ok(())
     }
 
-    async fn fetch_optical_info(&self) -> Result<OntMetrics> {
+    async fn fetch_optical_info(&self, prefetched: Option<OpticalPage>) -> Result<OntMetrics> {
+        let page = match prefetched {
+            Some(page) => page,
+            None => self.fetch_optical_page("optical").await?,
+        };
+        if page.body.trim().is_empty() {
+            return Err(anyhow!(
+                "Metrics page {} was empty, this firmware may need a query string in ONT_OPTICAL_QUERY",
+                self.optical_url()
+            ));
+        }
+        let html = page.body;
+        let parsed = if page.is_json || self.config.optical_format == OpticalFormat::Json {
+            parse_ont_metrics_json(&html, &self.config.optical)
+        } else {
+            parse_ont_metrics(&html, &self.config.optical)
+        };
+        parsed.map_err(|e| match self.config.log_html_snippet {
+            0 => e.context("Failed to parse metrics"),
+            max => e.context(format!("Failed to parse metrics, response began with {:?}", html_snippet(&html, max))),
+        })
+    }
+
+    // `page` labels the request: "session" when it doubles as the session check
+    async fn fetch_optical_page(&self, page: &str) -> Result<OpticalPage> {
         debug!("Fetching optical info");
         
-        let url = format!("{}/html/amp/opticinfo/opticinfo.asp", self.base_url);
-        let resp = self.client.get(&url).send().await?;
+        let url = self.optical_url();
+        let resp = self.send(page, self.get(&url)).await?;
         
+        if resp.status() == StatusCode::UNAUTHORIZED && self.config.login_mode == LoginMode::Basic {
+            return Err(CredentialsRejected.into());
+        }
         if !resp.status().is_success() {
-            return Err(anyhow!("Failed to fetch metrics page: {}", resp.status()));
+            return Err(UnexpectedStatus { what: "Metrics page request", status: resp.status() }.into());
         }
+        record_protocol(self.target(), &resp);
         
-        let html = resp.text().await?;
-        parse_ont_metrics(&html).context("Failed to parse metrics")
+        // Some firmware serves JSON from the .asp path too, so go by the content type as well
+        let is_json = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|ct| ct.contains("json"));
+        let body = self.read_text(page, resp).await?;
+        self.dump_page("optical", &body);
+        Ok(OpticalPage { body, is_json })
+    }
+
+    // ENABLE_DIAG: the optical readings from the CLI-over-HTTP endpoint of some models
+    async fn fetch_diag_optical_info(&self) -> Result<OntMetrics> {
+        let path = self.config.diag_path.as_deref().context("ENABLE_DIAG is set but ONT_DIAG_PATH is not")?;
+        debug!("Running diagnostic command {:?}", self.config.diag_command);
+
+        let req = self.client.post(format!("{}{}", self.base_url, path))
+            .header("Referer", format!("{}/", self.base_url))
+            .form(&[(self.config.diag_field.as_str(), self.config.diag_command.as_str())]);
+        let resp = self.send("diag", req).await?;
+        if !resp.status().is_success() {
+            return Err(UnexpectedStatus { what: "Diagnostic command request", status: resp.status() }.into());
+        }
+
+        let text = self.read_text("diag", resp).await?;
+        self.dump_page("diag", &text);
+        if is_login_page(&text) {
+            return Err(SessionExpired.into());
+        }
+        parse_diag_optic(&text).context("Failed to parse diagnostic command output")
+    }
+
+    // Write the raw page to DUMP_HTML_DIR (when set) so it can be attached to bug reports
+    fn dump_page(&self, page: &str, html: &str) {
+        let Some(dir) = &self.config.dump_html_dir else {
+            return;
+        };
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+        let path = Path::new(dir).join(format!("{}-{}.html", millis, page));
+        match std::fs::write(&path, html) {
+            Ok(()) => debug!("Dumped {} page to {}", page, path.display()),
+            Err(e) => error!("Failed to dump {} page to {}: {}", page, path.display(), e),
+        }
     }
 
     // Fetch device information page
     async fn fetch_device_info(&self) -> Result<String> {
         debug!("Fetching device info");
-        
-        // Common paths for device info on Huawei ONTs
-        let paths = [
-            "/html/ssmp/deviceinfo/deviceinfo.asp",
-            "/html/amp/deviceinfo/deviceinfo.asp",
-            "/html/amp/basic/deviceinfo.asp", 
-            "/html/advance/deviceinfo/deviceinfo.asp",
-        ];
-        
-        for path in &paths {
-            let url = format!("{}{}", self.base_url, path);
-            match self.client.get(&url).send().await {
-                Ok(resp) => match resp.text().await {
-                    Ok(html) if !html.is_empty() && !html.contains("404") && html.contains("stDeviceInfo") => {
-                        return Ok(html);
-                    }
-                    _ => continue,
-                },
-                _ => continue,
-            }
-        }
-        
-        Err(anyhow!("Could not fetch device info from any known path"))
+        self.fetch_first_available("device", DEVICE_INFO_PATHS, |html| html.contains("stDeviceInfo"))
+            .await
+            .context("Could not fetch device info from any known path")
+    }
+
+    async fn fetch_uptime_info(&self) -> Result<String> {
+        debug!("Fetching uptime info");
+        self.fetch_first_available("uptime", UPTIME_INFO_PATHS, |body| parse_uptime_page(body).is_some())
+            .await
+            .context("Could not fetch uptime info from any known path")
     }
 
     // Fetch WAN/internet status page
     async fn fetch_wan_info(&self) -> Result<String> {
         debug!("Fetching WAN info");
-        
-        let paths = [
-            "/html/bbsp/waninfo/waninfo.asp",
-            "/html/amp/internet/internet.asp",
-            "/html/amp/wan/wan.asp",
-            "/html/advance/internet/internet.asp",
-        ];
-        
-        for path in &paths {
-            let url = format!("{}{}", self.base_url, path);
-            match self.client.get(&url).send().await {
-                Ok(resp) => match resp.text().await {
-                    Ok(html) if !html.is_empty() && !html.contains("404") => {
-                        return Ok(html);
-                    }
-                    _ => continue,
-                },
-                _ => continue,
+        self.fetch_first_available("wan", WAN_INFO_PATHS, |_| true)
+            .await
+            .context("Could not fetch WAN info from any known path")
+    }
+
+    // Fetch LAN/WiFi clients page
+    async fn collect_wlan_info(&self, result: &mut OntMetrics) -> Result<bool> {
+        match self.fetch_wlan_info().await {
+            Ok(wlan_html) => {
+                let ssids = parse_wlan_page(&wlan_html);
+                if !ssids.is_empty() {
+                    debug!("WLAN info parsed successfully");
+                    result.wifi_ssids = ssids;
+                    return Ok(true);
+                }
+                debug!("Failed to parse WLAN info: no stWlan entries");
             }
+            Err(e) if is_session_expired(&e) => return Err(e),
+            Err(e) => debug!("Failed to fetch WLAN info: {}", e),
         }
-        
-        Err(anyhow!("Could not fetch WAN info from any known path"))
+        Ok(false)
+    }
+
+    async fn fetch_wlan_info(&self) -> Result<String> {
+        debug!("Fetching WLAN info");
+        self.fetch_first_available("wlan", WLAN_INFO_PATHS, |html| html.contains("stWlan"))
+            .await
+            .context("Could not fetch WLAN info from any known path")
     }
 
-    // Fetch LAN/WiFi clients page
     async fn fetch_lan_info(&self) -> Result<String> {
         debug!("Fetching LAN info");
-        
-        let paths = [
-            "/html/bbsp/common/GetLanUserDevInfo.asp",
-            "/html/amp/lanuser/lanuser.asp",
-            "/html/amp/user/user.asp",
-            "/html/advance/user/user.asp",
-        ];
-        
-        for path in &paths {
+        self.fetch_first_available("lan", LAN_INFO_PATHS, |_| true)
+            .await
+            .context("Could not fetch LAN info from any known path")
+    }
+
+    // Try each candidate path in turn, starting with the one that worked last time
+    async fn fetch_first_available(
+        &self,
+        page: &'static str,
+        paths: &[&'static str],
+        content_check: impl Fn(&str) -> bool,
+    ) -> Result<String> {
+        let remembered = self.path_cache.get(page);
+        let ordered = remembered
+            .into_iter()
+            .chain(paths.iter().copied().filter(|p| Some(*p) != remembered));
+
+        let mut attempts = Vec::new();
+        for path in ordered {
             let url = format!("{}{}", self.base_url, path);
-            match self.client.get(&url).send().await {
-                Ok(resp) => match resp.text().await {
-                    Ok(html) if !html.is_empty() && !html.contains("404") => {
-                        return Ok(html);
+            let mut outcome = String::new();
+            for attempt in 0..=self.config.path_retries {
+                let resp = match self.send(page, self.get(&url)).await {
+                    Ok(resp) => resp,
+                    Err(e) => {
+                        debug!("{} page {} (attempt {}): request failed: {}", page, path, attempt + 1, e);
+                        outcome = format!("request failed: {}", e);
+                        continue;
+                    }
+                };
+                let status = resp.status();
+                match self.read_text(page, resp).await {
+                    // Another path won't help, every page redirects to the login
+                    Ok(html) if is_login_page(&html) => {
+                        attempts.push(PathAttempt { path, outcome: "login page".to_string() });
+                        self.path_cache.record_attempts(page, attempts);
+                        return Err(SessionExpired.into());
+                    }
+                    Ok(html) => {
+                        let accepted = !html.is_empty() && !html.contains("404") && content_check(&html);
+                        debug!("{} page {}: status {}, content check {}", page, path, status, if accepted { "passed" } else { "failed" });
+                        PAGE_AVAILABLE.with_label_values(&[self.target(), path]).set(accepted as i64);
+                        if accepted {
+                            attempts.push(PathAttempt { path, outcome: "ok".to_string() });
+                            self.path_cache.record_attempts(page, attempts);
+                            self.dump_page(page, &html);
+                            self.path_cache.set(page, path);
+                            return Ok(html);
+                        }
+                        outcome = format!("status {}, content check failed", status.as_u16());
+                    }
+                    Err(e) => {
+                        debug!("{} page {}: status {}, failed to read body: {}", page, path, status, e);
+                        outcome = format!("status {}, failed to read body: {:#}", status.as_u16(), e);
                     }
-                    _ => continue,
-                },
-                _ => continue,
+                }
+                // Only transport failures are retried, a wrong page won't get better
+                break;
             }
+            attempts.push(PathAttempt { path, outcome });
         }
-        
-        Err(anyhow!("Could not fetch LAN info from any known path"))
+
+        self.path_cache.record_attempts(page, attempts);
+        Err(anyhow!("no candidate path matched for {} page", page))
     }
 
     async fn logout(&self) -> Result<()> {
         debug!("Logging out");
-        let url = format!("{}/logout.cgi?RequestFile=html/logout.html", self.base_url);
-        let _ = self.client.get(&url).send().await;
+        let url = format!("{}{}", self.base_url, self.config.logout_path);
+        let _ = self.send("logout", self.client.request(self.config.logout_method.clone(), &url)).await;
         Ok(())
     }
 }
 
+// Chinese-locale firmware serves some pages as GBK/GB2312. The charset comes from
+// the Content-Type header, then a <meta> tag, and defaults to UTF-8; a BOM wins
+// over both and is stripped.
+fn decode_body(body: &[u8], content_type: Option<&str>) -> String {
+    use regex::Regex;
+
+    let charset_re = Regex::new(r#"(?i)charset\s*=\s*["']?([\w.:-]+)"#).unwrap();
+    let label = |text: &str| charset_re.captures(text).map(|caps| caps[1].to_string());
+
+    let head = String::from_utf8_lossy(&body[..body.len().min(1024)]);
+    let encoding = content_type
+        .and_then(label)
+        .or_else(|| label(&head))
+        .and_then(|name| encoding_rs::Encoding::for_label(name.as_bytes()))
+        .unwrap_or(encoding_rs::UTF_8);
+
+    let (text, _, _) = encoding.decode(body);
+    text.into_owned()
+}
+
+// One response per scrape is enough, the connection is reused for the others.
+// reqwest doesn't expose the negotiated TLS version, only whether TLS was used.
+fn record_protocol(target: &str, resp: &Response) {
+    let tls = resp.url().scheme() == "https";
+    reset_target(&CLIENT_PROTOCOL_INFO, target);
+    CLIENT_PROTOCOL_INFO
+        .with_label_values(&[target, format!("{:?}", resp.version()).as_str(), if tls { "true" } else { "false" }])
+        .set(1);
+}
+
+fn encode_password(password: &str, encoding: PasswordEncoding) -> String {
+    match encoding {
+        PasswordEncoding::Base64 => BASE64_STANDARD.encode(password),
+        PasswordEncoding::Base64Url => BASE64_URL_SAFE.encode(password),
+        PasswordEncoding::Base64UrlNoPad => BASE64_URL_SAFE_NO_PAD.encode(password),
+    }
+}
+
+fn load_ca_bundle(path: &str) -> Result<Vec<Certificate>> {
+    let pem = std::fs::read(path).with_context(|| format!("Failed to read CA bundle {}", path))?;
+    let certs = Certificate::from_pem_bundle(&pem)
+        .with_context(|| format!("Failed to parse CA bundle {}", path))?;
+    if certs.is_empty() {
+        return Err(anyhow!("CA bundle {} contains no certificates", path));
+    }
+    Ok(certs)
+}
+
+// Client certificate + private key (PEM files) for ONTs that require mutual TLS
+fn load_identity(cert_path: &str, key_path: &str) -> Result<Identity> {
+    let mut pem = std::fs::read(cert_path)
+        .with_context(|| format!("Failed to read client certificate {}", cert_path))?;
+    let key = std::fs::read(key_path)
+        .with_context(|| format!("Failed to read client key {}", key_path))?;
+    pem.push(b'\n');
+    pem.extend_from_slice(&key);
+
+    Identity::from_pem(&pem)
+        .with_context(|| format!("Failed to parse client certificate {} / key {}", cert_path, key_path))
+}
+
+// Tokens are short hex/alphanumeric strings; anything else (typically an HTML
+// error page on a locked session) must not be sent on to login.cgi
+const MAX_TOKEN_LEN: usize = 128;
+
+fn validate_login_token(text: &str) -> Result<String> {
+    let token = text.trim_start_matches('\u{feff}').trim();
+
+    if token.is_empty() {
+        return Err(anyhow!("GetRandCount returned an empty token"));
+    }
+    if token.len() > MAX_TOKEN_LEN || !token.chars().all(|c| c.is_ascii_alphanumeric()) {
+        let snippet: String = token.chars().take(64).collect();
+        return Err(anyhow!(
+            "GetRandCount returned an unexpected response ({} bytes) instead of a token: {:?}",
+            token.len(),
+            snippet
+        ));
+    }
+
+    Ok(token.to_string())
+}
+
 // Helper structs for additional page parsing
 
 pub struct DevicePageInfo {
@@ -264,26 +1040,27 @@ pub struct DevicePageInfo {
     pub version: Option<String>,
     pub hardware_version: Option<String>,
     pub mac: Option<String>,
+    pub customization: Option<String>,
     pub uptime: Option<u64>,
-}
-
-pub struct WanPageInfo {
-    pub status: Option<String>,
-    pub ip: Option<String>,
-    pub rx_bytes: Option<u64>,
-    pub tx_bytes: Option<u64>,
+    pub cpu_usage: Option<f64>,
+    pub memory_usage: Option<f64>,
+    pub time_synced: Option<bool>,
+    pub device_time: Option<i64>,
 }
 
 pub struct ClientPageInfo {
     pub lan_count: Option<u32>,
     pub wifi_count: Option<u32>,
     pub total_count: Option<u32>,
+    pub declared_count: Option<u32>,
+    pub leases: BTreeMap<String, u64>,
+    pub rssi: Vec<ClientRssi>,
 }
 
 // Parse device info page
 fn parse_device_info_page(html: &str) -> Result<DevicePageInfo> {
     use regex::Regex;
-    use crate::parser::decode_hex_escapes;
+    use crate::parser::{decode_field_escapes, find_uptime_field, parse_datetime};
     
     let mut info = DevicePageInfo {
         model: None,
@@ -291,7 +1068,12 @@ fn parse_device_info_page(html: &str) -> Result<DevicePageInfo> {
         version: None,
         hardware_version: None,
         mac: None,
+        customization: None,
         uptime: None,
+        cpu_usage: None,
+        memory_usage: None,
+        time_synced: None,
+        device_time: None,
     };
     
     // Parse stDeviceInfo array: new stDeviceInfo("domain","serial","hardware","software","model",...)
@@ -299,115 +1081,663 @@ fn parse_device_info_page(html: &str) -> Result<DevicePageInfo> {
     if let Some(caps) = Regex::new(r#"new stDeviceInfo\(([^)]+)\)"#).unwrap().captures(html) {
         let args_str = caps.get(1).unwrap().as_str();
         let args: Vec<&str> = args_str.split(',').collect();
+        // Huawei pads fields with (escaped) spaces, which mustn't end up in labels
+        let field = |i: usize| decode_field_escapes(args[i].trim().trim_matches('"')).trim().to_string();
         
         if args.len() >= 2 {
-            info.serial = Some(decode_hex_escapes(args[1].trim().trim_matches('"')));
+            info.serial = Some(field(1));
         }
         if args.len() >= 3 {
-            info.hardware_version = Some(decode_hex_escapes(args[2].trim().trim_matches('"')));
+            info.hardware_version = Some(field(2));
         }
         if args.len() >= 4 {
-            info.version = Some(decode_hex_escapes(args[3].trim().trim_matches('"')));
+            info.version = Some(field(3));
         }
         if args.len() >= 5 {
-            info.model = Some(decode_hex_escapes(args[4].trim().trim_matches('"')));
+            info.model = Some(field(4));
         }
         if args.len() >= 8 {
             // MAC address is at position 8 (index 7)
-            let mac_encoded = args[7].trim().trim_matches('"');
-            let mac = decode_hex_escapes(mac_encoded);
-            info.mac = Some(mac);
+            info.mac = Some(field(7));
+        }
+        if args.len() >= 9 {
+            // ISP customization tag (carrier code) is at position 9 (index 8)
+            let custom = field(8);
+            if !custom.is_empty() {
+                info.customization = Some(custom);
+            }
         }
     }
     
-    // Uptime patterns (from optical info or separate calls)
-    let uptime_patterns = [
-        r"UpTime[=:]\s*(\d+)",
-        r"new stDeviceInfo\([^)]*(\d{5,})[^)]*\)",
-    ];
+    // Only some models/status pages report these, e.g. var CpuUsed = '8%'; var MemUsed = '43';
+    info.cpu_usage = parse_usage_percent(html, "Cpu");
+    info.memory_usage = parse_usage_percent(html, "Mem(?:ory)?");
     
-    for pattern in &uptime_patterns {
-        if let Some(uptime) = Regex::new(pattern)
+    // Uptime (from optical info or separate calls), in whatever format the model uses
+    info.uptime = find_uptime_field(html).or_else(|| {
+        Regex::new(r"new stDeviceInfo\([^)]*(\d{5,})[^)]*\)")
             .unwrap()
             .captures(html)
             .and_then(|caps| caps.get(1))
             .and_then(|m| m.as_str().parse::<u64>().ok())
-        {
-            info.uptime = Some(uptime);
-            break;
-        }
-    }
+    });
+    
+    // Clock state, only on some firmware: var NtpStatus = "Synchronized"; CurrentLocalTime = "2024-05-01 12:34:56"
+    info.time_synced = parse_time_synced(html);
+    info.device_time = Regex::new(r#"(?i)\b(?:CurrentLocalTime|CurrentTime|SystemTime)["']?\s*[=:]\s*["']([^"']+)["']"#)
+        .unwrap()
+        .captures(html)
+        .and_then(|caps| parse_datetime(&decode_field_escapes(&caps[1])));
     
     Ok(info)
 }
 
-// Parse WAN info page
-fn parse_wan_page(html: &str) -> Result<WanPageInfo> {
+fn parse_time_synced(html: &str) -> Option<bool> {
     use regex::Regex;
     
-    let mut wan = WanPageInfo {
-        status: None,
-        ip: None,
-        rx_bytes: None,
-        tx_bytes: None,
-    };
+    let value = Regex::new(r#"(?i)\b(?:X_HW_)?(?:Ntp|TimeSync)\w*Status["']?\s*[=:]\s*["']?(\w+)"#)
+        .unwrap()
+        .captures(html)?
+        .get(1)?
+        .as_str()
+        .to_ascii_lowercase();
     
-    // Look for WAN status in CurrentWan object
-    if let Some(caps) = Regex::new(r#"CurrentWan\.Status\s*=\s*['"]([^'"]+)['"]"#).unwrap().captures(html) {
-        wan.status = Some(caps.get(1).unwrap().as_str().to_string());
+    match value.as_str() {
+        "synchronized" | "synced" | "sync" | "success" | "1" | "true" => Some(true),
+        "unsynchronized" | "notsynchronized" | "unsynced" | "disabled" | "error" | "fail" | "failed" | "0" | "false" => Some(false),
+        _ => None,
     }
+}
+
+// Percentage assigned to <prefix>Used / <prefix>Usage / <prefix>Util..., e.g. CpuUsed = "8%"
+fn parse_usage_percent(html: &str, prefix: &str) -> Option<f64> {
+    use regex::Regex;
     
-    // Look for IPv4 IP address
-    if let Some(caps) = Regex::new(r#"IPv4IPAddress\s*=\s*['"](\d+\.\d+\.\d+\.\d+)['"]"#).unwrap().captures(html) {
-        wan.ip = Some(caps.get(1).unwrap().as_str().to_string());
+    let re = Regex::new(&format!(
+        r#"(?i)\b{}_?(?:Used|Usage|Util\w*|Rate|Percent)["']?\s*[=:]\s*["']?(\d+(?:\.\d+)?)\s*%?"#,
+        prefix
+    ))
+    .unwrap();
+    re.captures(html)
+        .and_then(|caps| caps.get(1))
+        .and_then(|m| m.as_str().parse::<f64>().ok())
+        .filter(|pct| (0.0..=100.0).contains(pct))
+}
+
+// Parse WAN info page. Each connection is rendered with its TR-098 domain
+// (...WANConnectionDevice.N.WANPPPConnection.M) followed by its fields, so the
+// page is split on those and every chunk is searched for the fields it needs.
+fn parse_wan_page(html: &str) -> Result<Vec<WanConnection>> {
+    use regex::Regex;
+    
+    let domain_re = Regex::new(
+        r"InternetGatewayDevice\.WANDevice\.\d+\.WANConnectionDevice\.\d+\.WAN(?:IP|PPP)Connection\.\d+",
+    )
+    .unwrap();
+    
+    // Fields of the same connection can be spread over several arrays on the page
+    let starts: Vec<_> = domain_re.find_iter(html).collect();
+    let mut chunks: Vec<(&str, String)> = Vec::new();
+    for (n, m) in starts.iter().enumerate() {
+        let end = starts.get(n + 1).map_or(html.len(), |next| next.start());
+        let chunk = &html[m.end()..end];
+        match chunks.iter_mut().find(|(domain, _)| *domain == m.as_str()) {
+            Some((_, text)) => text.push_str(chunk),
+            None => chunks.push((m.as_str(), chunk.to_string())),
+        }
     }
     
-    // Alternative: from AddressList
-    if wan.ip.is_none()
-        && let Some(caps) = Regex::new(r#"IPAddress['"]\s*[=:]\s*['"](\d+\.\d+\.\d+\.\d+)['"]"#).unwrap().captures(html)
+    let mut connections: Vec<WanConnection> = chunks
+        .iter()
+        .enumerate()
+        .map(|(n, (_, text))| {
+            let name = wan_field(text, &["Name"]).unwrap_or_else(|| format!("wan{}", n + 1));
+            let service = wan_field(text, &["X_HW_SERVICELIST", "ServiceList"])
+                .unwrap_or_else(|| wan_service_from_name(&name).to_string());
+            WanConnection {
+                service,
+                status: wan_field(text, &["ConnectionStatus", "Status"]),
+                ip: wan_field(text, &["ExternalIPAddress", "IPv4IPAddress", "IPAddress"])
+                    .filter(|ip| !ip.is_empty()),
+                rx_bytes: wan_field(text, &["BytesReceived", "RXBytes"]).and_then(|v| v.parse().ok()),
+                tx_bytes: wan_field(text, &["BytesSent", "TXBytes"]).and_then(|v| v.parse().ok()),
+                last_error: wan_field(text, &["LastConnectionError", "LastError"]).filter(|e| !e.is_empty()),
+                name,
+            }
+        })
+        .collect();
+    
+    // Single-WAN pages only expose the current connection
+    if connections.is_empty()
+        && let Some(caps) = Regex::new(r#"CurrentWan\.Status\s*=\s*['"]([^'"]+)['"]"#).unwrap().captures(html)
     {
-        wan.ip = Some(caps.get(1).unwrap().as_str().to_string());
+        let ip = Regex::new(r#"IPv4IPAddress\s*=\s*['"](\d+\.\d+\.\d+\.\d+)['"]"#)
+            .unwrap()
+            .captures(html)
+            .or_else(|| Regex::new(r#"IPAddress['"]\s*[=:]\s*['"](\d+\.\d+\.\d+\.\d+)['"]"#).unwrap().captures(html))
+            .map(|caps| caps.get(1).unwrap().as_str().to_string());
+        connections.push(WanConnection {
+            name: "wan".to_string(),
+            service: "unknown".to_string(),
+            status: Some(caps.get(1).unwrap().as_str().to_string()),
+            ip,
+            rx_bytes: None,
+            tx_bytes: None,
+            last_error: None,
+        });
     }
     
-    Ok(wan)
+    Ok(connections)
+}
+
+// First of `keys` assigned in `text` as `Key: "value"`, `Key = 'value'` or `"Key":"value"`
+fn wan_field(text: &str, keys: &[&str]) -> Option<String> {
+    use regex::Regex;
+    use crate::parser::decode_hex_escapes;
+    
+    keys.iter().find_map(|key| {
+        let re = Regex::new(&format!(r#"(?:^|[^A-Za-z0-9_.]){}["']?\s*[=:]\s*["']([^"']*)["']"#, key)).unwrap();
+        re.captures(text).map(|caps| decode_hex_escapes(caps.get(1).unwrap().as_str()).trim().to_string())
+    })
+}
+
+// Connection names look like "1_INTERNET_R_VID_100" or "2_VOIP_B_VID_200"
+fn wan_service_from_name(name: &str) -> &'static str {
+    let upper = name.to_ascii_uppercase();
+    ["TR069", "INTERNET", "VOIP", "IPTV", "OTHER"]
+        .into_iter()
+        .find(|service| upper.contains(service))
+        .unwrap_or("unknown")
 }
 
 // Parse LAN/WiFi clients page
+// USERDevice(domain, IpAddr, MacAddr, Port, IpType, DevType, DevStatus, PortType, Time, HostName, ...)
+// For DHCP clients Time is the remaining lease in seconds; static clients have no lease.
+// The page can list hundreds of clients, so each entry is split and classified in one pass.
 fn parse_lan_page(html: &str) -> Result<ClientPageInfo> {
     use regex::Regex;
+    use crate::parser::{decode_hex_escapes, split_js_args};
+    
+    const MAC: usize = 2;
+    const IP_TYPE: usize = 4;
+    const LEASE: usize = 8;
+    
+    let clean = |s: &str| decode_hex_escapes(s.trim().trim_matches('"')).trim().to_string();
+    // The Port is "LAN1".."LAN4" for wired clients and "SSID1".. for WiFi ones
+    let is_port = |arg: &str, prefix: &str| {
+        let arg = arg.trim().trim_matches('"');
+        arg.strip_prefix(prefix).is_some_and(|n| n.bytes().all(|b| b.is_ascii_digit()))
+    };
     
     let mut clients = ClientPageInfo {
         lan_count: None,
         wifi_count: None,
         total_count: None,
+        declared_count: None,
+        leases: BTreeMap::new(),
+        rssi: Vec::new(),
     };
+    let (mut total, mut lan, mut wifi) = (0u32, 0u32, 0u32);
     
-    // Count USERDevice entries in the array
-    let user_device_re = Regex::new(r"new\s+(?:USERDevice|USERDeviceNew)\(").unwrap();
-    let total_count = user_device_re.find_iter(html).count() as u32;
-    
-    if total_count > 0 {
-        clients.total_count = Some(total_count);
-        
-        // Parse actual array entries to count LAN vs WiFi
-        // Array entries look like: new USERDevice("...","...","...","LAN2",...)
-        // The Port is the 4th parameter (index 3)
-        let lan_count = Regex::new(r#"new\s+(?:USERDevice|USERDeviceNew)\([^)]*"(LAN\d*)"[^)]*\)"#)
-            .unwrap()
-            .find_iter(html)
-            .count() as u32;
-        let wifi_count = Regex::new(r#"new\s+(?:USERDevice|USERDeviceNew)\([^)]*"(SSID\d*)"[^)]*\)"#)
-            .unwrap()
-            .find_iter(html)
-            .count() as u32;
+    for caps in Regex::new(r"new\s+(?:USERDevice|USERDeviceNew)\(([^)]*)\)").unwrap().captures_iter(html) {
+        let args = split_js_args(caps.get(1).unwrap().as_str());
+        total += 1;
+        if args.iter().any(|arg| is_port(arg, "LAN")) {
+            lan += 1;
+        }
+        if let Some(ssid) = args.iter().find(|arg| is_port(arg, "SSID")) {
+            wifi += 1;
+            // Not at a fixed position across firmware, but the only negative field
+            let rssi = args.iter().find_map(|arg| parse_rssi(&clean(arg)));
+            let mac = clean(args.get(MAC).copied().unwrap_or_default());
+            if let Some(rssi_dbm) = rssi
+                && !mac.is_empty()
+            {
+                clients.rssi.push(ClientRssi { mac, ssid: clean(ssid), rssi_dbm });
+            }
+        }
         
-        if lan_count > 0 {
-            clients.lan_count = Some(lan_count);
+        if args.len() <= LEASE || !clean(args[IP_TYPE]).eq_ignore_ascii_case("DHCP") {
+            continue;
         }
-        if wifi_count > 0 {
-            clients.wifi_count = Some(wifi_count);
+        let mac = clean(args[MAC]);
+        if let Ok(lease) = clean(args[LEASE]).parse::<u64>()
+            && !mac.is_empty()
+        {
+            clients.leases.insert(mac, lease);
         }
     }
     
+    if total > 0 {
+        clients.total_count = Some(total);
+        clients.lan_count = (lan > 0).then_some(lan);
+        clients.wifi_count = (wifi > 0).then_some(wifi);
+    }
+    
+    // e.g. var UserDevinfoNum = 12; next to the array, on firmware that renders one
+    clients.declared_count = Regex::new(r#"(?i)\bUserDev(?:ice)?(?:info)?(?:Num|Count)["']?\s*[=:]\s*["']?(\d+)"#)
+        .unwrap()
+        .captures(html)
+        .and_then(|caps| caps[1].parse().ok());
+    
     Ok(clients)
 }
+
+// ONT_LOG_HTML_SNIPPET: the start of a page for the error log, whitespace collapsed and
+// anything that could be a credential (token, cookie, base64 blob) redacted
+fn html_snippet(html: &str, max_bytes: usize) -> String {
+    use regex::Regex;
+    
+    let mut end = max_bytes.min(html.len());
+    while !html.is_char_boundary(end) {
+        end -= 1;
+    }
+    let text = html[..end].split_whitespace().collect::<Vec<_>>().join(" ");
+    
+    let text = Regex::new(r#"(?i)\b(\w*(?:token|cookie|session|sid|pass(?:word)?|pwd|auth)\w*)(["']?\s*[=:]\s*["']?)[^"'\s;&,)]+"#)
+        .unwrap()
+        .replace_all(&text, "$1$2[redacted]");
+    // Only runs mixing letters and digits, so names like InternetGatewayDevice stay readable
+    Regex::new(r"[A-Za-z0-9+/_-]{20,}={0,2}")
+        .unwrap()
+        .replace_all(&text, |caps: &regex::Captures| {
+            let run = &caps[0];
+            let mixed = run.bytes().any(|b| b.is_ascii_digit()) && run.bytes().any(|b| b.is_ascii_alphabetic());
+            if mixed { "[redacted]".to_string() } else { run.to_string() }
+        })
+        .into_owned()
+}
+
+// "-65" or "-65dBm"; 0 and anything outside the radio range means no reading
+fn parse_rssi(s: &str) -> Option<i64> {
+    let value = s.trim().trim_end_matches("dBm").trim_end().strip_prefix('-')?;
+    if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let rssi = -value.parse::<i64>().ok()?;
+    (-120..0).contains(&rssi).then_some(rssi)
+}
+
+// Parse the WLAN basic config page:
+// new stWlan(domain, name, enable, ssid, BeaconType, BasicEncrypt, ...)
+fn parse_wlan_page(html: &str) -> Vec<WifiSsid> {
+    use regex::Regex;
+    use crate::parser::{decode_hex_escapes, split_js_args};
+
+    let clean = |s: &str| decode_hex_escapes(s.trim().trim_matches('"')).trim().to_string();
+
+    Regex::new(r"new stWlan\(([^)]+)\)")
+        .unwrap()
+        .captures_iter(html)
+        .filter_map(|caps| {
+            let args = split_js_args(caps.get(1).unwrap().as_str());
+            if args.len() < 6 {
+                return None;
+            }
+            let domain = clean(args[0]);
+            Some(WifiSsid {
+                ssid: clean(args[3]),
+                enabled: clean(args[2]) == "1",
+                security: wlan_security(&clean(args[4]), &clean(args[5])),
+                band: wlan_band(&domain),
+            })
+        })
+        .collect()
+}
+
+// Map the TR-098 BeaconType (plus BasicEncryptionModes for "Basic") to a short name
+fn wlan_security(beacon_type: &str, basic_encrypt: &str) -> String {
+    match beacon_type {
+        "None" => "open".to_string(),
+        "Basic" if basic_encrypt == "None" => "open".to_string(),
+        "Basic" => "wep".to_string(),
+        "WPA" => "wpa".to_string(),
+        "11i" => "wpa2".to_string(),
+        "WPAand11i" => "wpa-wpa2".to_string(),
+        "WPA3" => "wpa3".to_string(),
+        "11iandWPA3" => "wpa2-wpa3".to_string(),
+        other => other.to_ascii_lowercase(),
+    }
+}
+
+// Huawei numbers the 2.4GHz SSIDs WLANConfiguration.1-4 and the 5GHz ones 5-8
+fn wlan_band(domain: &str) -> &'static str {
+    let index = domain
+        .rsplit_once("WLANConfiguration.")
+        .and_then(|(_, rest)| rest.split('.').next())
+        .and_then(|n| n.parse::<u32>().ok());
+    match index {
+        Some(1..=4) => "2.4GHz",
+        Some(_) => "5GHz",
+        None => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_body() {
+        // "光猫" in GBK
+        let gbk = [0xb9, 0xe2, 0xc3, 0xa8];
+        assert_eq!(decode_body(&gbk, Some("text/html; charset=GBK")), "光猫");
+
+        let mut page = b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=gb2312\">".to_vec();
+        page.extend_from_slice(&gbk);
+        assert!(decode_body(&page, Some("text/html")).ends_with("光猫"));
+
+        assert_eq!(decode_body("\u{feff}abc".as_bytes(), None), "abc");
+        assert_eq!(decode_body("光猫".as_bytes(), None), "光猫");
+    }
+
+    #[test]
+    fn test_encode_password() {
+        // "??>" is 0x3f 0x3f 0x3e, which hits the '+' slot in the standard alphabet
+        assert_eq!(encode_password("??>", PasswordEncoding::Base64), "Pz8+");
+        assert_eq!(encode_password("??>", PasswordEncoding::Base64Url), "Pz8-");
+        assert_eq!(encode_password("pass", PasswordEncoding::Base64UrlNoPad), "cGFzcw");
+    }
+
+    #[test]
+    fn test_error_reason() {
+        let auth = anyhow::Error::new(CredentialsRejected).context("Failed to login");
+        assert_eq!(error_reason(&auth), "auth");
+
+        let status = UnexpectedStatus { what: "Metrics page request", status: StatusCode::BAD_GATEWAY };
+        let http = anyhow::Error::new(status).context("Failed to fetch optical info");
+        assert_eq!(error_reason(&http), "http");
+
+        let parse = anyhow!("Failed to find stOpticInfo call").context("Failed to parse metrics");
+        assert_eq!(error_reason(&parse), "parse");
+    }
+
+    #[test]
+    fn test_parse_device_info_page_mixed_escapes() {
+        let html = r#"var stDeviceInfos = new Array(new stDeviceInfo("InternetGatewayDevice.DeviceInfo","4857544345AABBCC","26AD%2eA","V5R020C10S254","HG8145V5%20Pro","","","00\x3a11\x3a22\x3a33\x3a44\x3a55","COMMON"),null);"#;
+
+        let info = parse_device_info_page(html).unwrap();
+        assert_eq!(info.hardware_version.as_deref(), Some("26AD.A"));
+        assert_eq!(info.model.as_deref(), Some("HG8145V5 Pro"));
+        assert_eq!(info.mac.as_deref(), Some("00:11:22:33:44:55"));
+        assert_eq!(info.customization.as_deref(), Some("COMMON"));
+    }
+
+    #[test]
+    fn test_parse_device_info_page_trims_padding() {
+        let html = r#"var stDeviceInfos = new Array(new stDeviceInfo("InternetGatewayDevice.DeviceInfo","2416R080776AS\x20\x20","26AD\x2eA\x20","V5R020C10S254","HUAWEI\x20\x20\x20\x20","","","00\x3a11\x3a22\x3a33\x3a44\x3a55","\x20\x20"),null);"#;
+
+        let info = parse_device_info_page(html).unwrap();
+        assert_eq!(info.serial.as_deref(), Some("2416R080776AS"));
+        assert_eq!(info.hardware_version.as_deref(), Some("26AD.A"));
+        assert_eq!(info.model.as_deref(), Some("HUAWEI"));
+        assert_eq!(info.customization, None);
+    }
+
+    #[test]
+    fn test_parse_lan_page() {
+        let html = r#"
+        var UserDevInfo = new Array(new USERDevice("InternetGatewayDevice.LANDevice.1.X_HW_UserDev.1","192.168.100.10","aa:bb:cc:dd:ee:01","LAN1","DHCP","PC","Online","ETH","85000","laptop"),new USERDevice("InternetGatewayDevice.LANDevice.1.X_HW_UserDev.2","192.168.100.2","aa:bb:cc:dd:ee:02","SSID1","Static","PC","Online","WIFI","--","nas"),null);
+        "#;
+
+        let info = parse_lan_page(html).unwrap();
+        assert_eq!(info.total_count, Some(2));
+        assert_eq!(info.lan_count, Some(1));
+        assert_eq!(info.wifi_count, Some(1));
+        assert_eq!(info.leases.len(), 1);
+        assert_eq!(info.leases.get("aa:bb:cc:dd:ee:01"), Some(&85000));
+        assert!(info.rssi.is_empty());
+        assert_eq!(info.declared_count, None);
+        assert_eq!(parse_lan_page("var UserDevinfoNum = 3;").unwrap().declared_count, Some(3));
+
+        let html = r#"new USERDevice("InternetGatewayDevice.LANDevice.1.X_HW_UserDev.1","192.168.100.3","aa:bb:cc:dd:ee:03","SSID5","DHCP","PHONE","Online","WIFI","3600","phone","\x2d67"),new USERDevice("InternetGatewayDevice.LANDevice.1.X_HW_UserDev.2","192.168.100.4","aa:bb:cc:dd:ee:04","LAN2","DHCP","PC","Online","ETH","3600","pc","0")"#;
+        let info = parse_lan_page(html).unwrap();
+        assert_eq!(info.rssi, [ClientRssi {
+            mac: "aa:bb:cc:dd:ee:03".to_string(),
+            ssid: "SSID5".to_string(),
+            rssi_dbm: -67,
+        }]);
+        assert_eq!(parse_rssi("-71dBm"), Some(-71));
+        assert_eq!(parse_rssi("--"), None);
+    }
+
+    #[test]
+    fn test_parse_device_info_page_clock() {
+        let html = r#"var stDeviceInfos = new Array(new stDeviceInfo("InternetGatewayDevice.DeviceInfo","4857544345AABBCC"),null);
+        var NtpStatus = "Synchronized"; var CurrentLocalTime = "2024\x2d05\x2d01 12:34:56";"#;
+
+        let info = parse_device_info_page(html).unwrap();
+        assert_eq!(info.time_synced, Some(true));
+        assert_eq!(info.device_time, Some(1714566896));
+
+        assert_eq!(parse_time_synced(r#"X_HW_NTPStatus: "Unsynchronized""#), Some(false));
+        assert_eq!(parse_time_synced("var Status = 1;"), None);
+    }
+
+    #[test]
+    fn test_parse_usage_percent() {
+        let html = r#"var CpuUsed = '8%'; var MemUsed = "43.5";"#;
+        assert_eq!(parse_usage_percent(html, "Cpu"), Some(8.0));
+        assert_eq!(parse_usage_percent(html, "Mem(?:ory)?"), Some(43.5));
+        assert_eq!(parse_usage_percent(r#"MemoryUsage: "312""#, "Mem(?:ory)?"), None);
+        assert_eq!(parse_usage_percent("var UpTime = 100;", "Cpu"), None);
+    }
+
+    #[test]
+    fn test_parse_wan_page_multiple_connections() {
+        let html = r#"
+        var WanList = [
+            {domain:"InternetGatewayDevice.WANDevice.1.WANConnectionDevice.1.WANPPPConnection.1", Name:"1_INTERNET_R_VID_100", X_HW_SERVICELIST:"INTERNET", ConnectionStatus:"Connected", ExternalIPAddress:"100.64.12.34", LastConnectionError:"ERROR_AUTHENTICATION_FAILURE"},
+            {domain:"InternetGatewayDevice.WANDevice.1.WANConnectionDevice.2.WANIPConnection.1", Name:"2_VOIP_B_VID_200", ConnectionStatus:"Disconnected", ExternalIPAddress:""}
+        ];
+        var WanStats = [
+            {domain:"InternetGatewayDevice.WANDevice.1.WANConnectionDevice.1.WANPPPConnection.1", BytesReceived:"123456", BytesSent:"7890"}
+        ];
+        "#;
+
+        let connections = parse_wan_page(html).unwrap();
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[0].name, "1_INTERNET_R_VID_100");
+        assert_eq!(connections[0].service, "INTERNET");
+        assert_eq!(connections[0].status.as_deref(), Some("Connected"));
+        assert_eq!(connections[0].ip.as_deref(), Some("100.64.12.34"));
+        assert_eq!(connections[0].rx_bytes, Some(123456));
+        assert_eq!(connections[0].tx_bytes, Some(7890));
+        assert_eq!(connections[0].last_error.as_deref(), Some("ERROR_AUTHENTICATION_FAILURE"));
+        assert_eq!(connections[1].service, "VOIP");
+        assert_eq!(connections[1].last_error, None);
+        assert_eq!(connections[1].ip, None);
+        assert_eq!(connections[1].rx_bytes, None);
+    }
+
+    #[test]
+    fn test_parse_wlan_page() {
+        let html = r#"
+        var WlanList = new Array(new stWlan("InternetGatewayDevice.LANDevice.1.WLANConfiguration.1","wlan0","1","Home\x20WiFi","11i","None","None","TKIPEncryption","PSKAuthentication","AESEncryption","PSKAuthentication"),new stWlan("InternetGatewayDevice.LANDevice.1.WLANConfiguration.2","wlan1","0","Guest","None","None","None","TKIPEncryption","PSKAuthentication","AESEncryption","PSKAuthentication"),new stWlan("InternetGatewayDevice.LANDevice.1.WLANConfiguration.5","wlan4","1","Home-5G","WPAand11i","None","None","TKIPEncryption","PSKAuthentication","AESEncryption","PSKAuthentication"),null);
+        "#;
+
+        let ssids = parse_wlan_page(html);
+        assert_eq!(ssids.len(), 3);
+        assert_eq!(ssids[0], WifiSsid {
+            ssid: "Home WiFi".to_string(),
+            enabled: true,
+            security: "wpa2".to_string(),
+            band: "2.4GHz",
+        });
+        assert!(!ssids[1].enabled);
+        assert_eq!(ssids[1].security, "open");
+        assert_eq!(ssids[2].band, "5GHz");
+        assert_eq!(ssids[2].security, "wpa-wpa2");
+    }
+
+    #[test]
+    fn test_html_snippet() {
+        let html = "<html>\n  <script>var token = \"0a1b2c3d4e5f\"; document.cookie = \"Cookie=sid=abc\";</script>\n  <p>dXNlcjpzZWNyZXRwYXNzd29yZDEyMw==</p>\n</html>";
+
+        let snippet = html_snippet(html, 1000);
+        assert_eq!(
+            snippet,
+            r#"<html> <script>var token = "[redacted]"; document.cookie = "[redacted]";</script> <p>[redacted]</p> </html>"#
+        );
+        assert_eq!(html_snippet("new stOpticInfo(\"InternetGatewayDevice.X_HW_DEBUG\")", 100), "new stOpticInfo(\"InternetGatewayDevice.X_HW_DEBUG\")");
+        assert_eq!(html_snippet("<html>é</html>", 7), "<html>");
+    }
+
+    #[test]
+    fn test_is_login_page() {
+        assert!(is_login_page("<script>top.location.replace('/login.asp');</script>"));
+        assert!(is_login_page(r#"<script>window.location="/login.asp";</script>"#));
+        assert!(!is_login_page(r#"var WanList = new Array(); var LogoutUrl = "/login.asp";"#));
+    }
+
+    #[test]
+    fn test_validate_login_token() {
+        assert_eq!(validate_login_token("\u{feff}0a1b2c3d4e5f\r\n").unwrap(), "0a1b2c3d4e5f");
+    }
+
+    #[test]
+    fn test_validate_login_token_rejects_html() {
+        let body = r#"<html><head><title>Error</title></head><body>The user is locked, try again later.</body></html>"#;
+
+        let err = validate_login_token(body).unwrap_err();
+        assert!(err.to_string().contains("unexpected response"));
+        assert!(validate_login_token("  ").is_err());
+    }
+
+    // In-process stand-in for the ONT web UI: token, login form, session cookie and
+    // the optical page, which is only served to a logged-in session
+    async fn start_mock_ont() -> String {
+        use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+
+        async fn landing() -> HttpResponse {
+            HttpResponse::Ok().body("<html>login.asp</html>")
+        }
+        async fn rand_count() -> HttpResponse {
+            HttpResponse::Ok().body("\u{feff}0a1b2c3d4e5f")
+        }
+        async fn login(form: web::Form<HashMap<String, String>>) -> HttpResponse {
+            let accepted = form.get("x.X_HW_Token").map(String::as_str) == Some("0a1b2c3d4e5f")
+                && form.get("UserName").map(String::as_str) == Some("user")
+                && form.get("PassWord") == Some(&BASE64_STANDARD.encode("pass"));
+            if !accepted {
+                return HttpResponse::Ok().body("<script>window.location='/login.asp';</script>");
+            }
+            HttpResponse::Ok()
+                .insert_header(("Set-Cookie", "Cookie=sid=mock; Path=/"))
+                .body("<script>top.location.replace('/index.asp');</script>")
+        }
+        async fn optical(req: HttpRequest) -> HttpResponse {
+            if req.cookie("Cookie").is_none() {
+                return HttpResponse::Ok().body("<script>top.location.replace('/login.asp');</script>");
+            }
+            HttpResponse::Ok().body(r#"var opticInfos = new Array(new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","2.33","-24.09","3364","47","10"),null);"#)
+        }
+
+        let server = HttpServer::new(|| {
+            App::new()
+                .route("/", web::get().to(landing))
+                .route("/asp/GetRandCount.asp", web::post().to(rand_count))
+                .route("/login.cgi", web::post().to(login))
+                .route("/html/amp/opticinfo/opticinfo.asp", web::get().to(optical))
+                .route("/logout.cgi", web::get().to(HttpResponse::Ok))
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+        format!("http://{}", addr)
+    }
+
+    fn mock_config(url: &str) -> Config {
+        Config {
+            enable_device: false,
+            enable_wan: false,
+            enable_lan: false,
+            enable_wlan: false,
+            ..Config::for_test(url)
+        }
+    }
+
+    #[test]
+    fn test_path_cache_summary() {
+        let cache = PathCache::default();
+        cache.set("wlan", WLAN_INFO_PATHS[0]);
+        assert_eq!(
+            cache.summary(),
+            format!("device=none, uptime=none, wan=none, lan=none, wlan={}", WLAN_INFO_PATHS[0])
+        );
+    }
+
+    #[test]
+    fn test_optical_url() {
+        let url = "http://192.168.100.1";
+        let client = |query: Option<&str>, optical_format| {
+            let config = Config {
+                optical_query: query.map(str::to_string),
+                optical_format,
+                ..Config::for_test(url)
+            };
+            OntClient::new(&config, url, PathCache::default(), None).unwrap().optical_url()
+        };
+
+        assert_eq!(client(None, OpticalFormat::Asp), "http://192.168.100.1/html/amp/opticinfo/opticinfo.asp");
+        assert_eq!(
+            client(Some("RequestFile={path}"), OpticalFormat::Asp),
+            "http://192.168.100.1/html/amp/opticinfo/opticinfo.asp?RequestFile=html/amp/opticinfo/opticinfo.asp"
+        );
+        assert_eq!(client(Some("x=1"), OpticalFormat::Json), "http://192.168.100.1/api/ntwk/wan?type=optic&x=1");
+    }
+
+    #[actix_web::test]
+    async fn test_scrape_metrics_against_mock_ont() {
+        let url = start_mock_ont().await;
+        let config = mock_config(&url);
+
+        let client = OntClient::new(&config, &url, PathCache::default(), None).unwrap();
+        let metrics = client.scrape_metrics().await.unwrap();
+
+        assert!(metrics.optical_present);
+        assert_eq!(metrics.tx_power, 2.33);
+        assert_eq!(metrics.rx_power, -24.09);
+        assert_eq!(metrics.voltage, 3364.0);
+        assert_eq!(metrics.temperature, 47.0);
+        assert_eq!(metrics.bias_current, 10.0);
+    }
+
+    #[actix_web::test]
+    async fn test_kept_session_fetches_optical_page_once() {
+        let url = start_mock_ont().await;
+        let config = mock_config(&url);
+        let session = SessionStore::load("/nonexistent/ont-session", std::iter::once(&url));
+        let optical_requests = || {
+            ["session", "optical"]
+                .iter()
+                .map(|page| CLIENT_REQUESTS.with_label_values(&[url.as_str(), page]).get())
+                .sum::<u64>()
+        };
+
+        let client = OntClient::new(&config, &url, PathCache::default(), Some(&session)).unwrap();
+        client.scrape_metrics().await.unwrap();
+
+        // The session check returns the page, which is parsed without a second GET
+        let before = optical_requests();
+        let client = OntClient::new(&config, &url, PathCache::default(), Some(&session)).unwrap();
+        let metrics = client.scrape_metrics().await.unwrap();
+        assert_eq!(metrics.rx_power, -24.09);
+        assert_eq!(optical_requests() - before, 1);
+    }
+
+    #[actix_web::test]
+    async fn test_scrape_metrics_rejected_credentials() {
+        let url = start_mock_ont().await;
+        let config = Config {
+            ont_pass: "wrong".to_string(),
+            ..mock_config(&url)
+        };
+
+        let client = OntClient::new(&config, &url, PathCache::default(), None).unwrap();
+        let err = client.scrape_metrics().await.unwrap_err();
+        assert!(is_auth_error(&err));
+    }
+}
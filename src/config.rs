@@ -0,0 +1,650 @@
+use crate::metrics::DEVICE_INFO_LABELS;
+use crate::parser::{OpticalIndexOverrides, OpticalOptions};
+use ipnet::IpNet;
+use std::env;
+use std::net::SocketAddr;
+use tokio::time::MissedTickBehavior;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoginMode {
+    // GetRandCount.asp token + login.cgi form (HG8145V5 and most current models)
+    Cgi,
+    // Plain HTTP Basic auth on every page (older models such as HG8010)
+    Basic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpticalFormat {
+    // opticinfo.asp with an embedded stOpticInfo(...) call
+    Asp,
+    // JSON web API of newer firmware
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PasswordEncoding {
+    // Standard alphabet with padding (most firmware)
+    Base64,
+    // URL-safe alphabet with padding
+    Base64Url,
+    // URL-safe alphabet without padding
+    Base64UrlNoPad,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenPlacement {
+    // x.X_HW_Token form field on the login POST
+    Form,
+    // x.X_HW_Token cookie alongside Language=english (some firmware variants)
+    Cookie,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WanIpMask {
+    // WAN IP exported as-is
+    Off,
+    // Replaced by the literal "redacted"
+    Redact,
+    // Replaced by a short SipHash keyed with MASK_WAN_IP_SALT, so a changed IP still
+    // shows up but can't be recovered by hashing every IPv4 address without the salt
+    Hash { key: (u64, u64) },
+}
+
+// Names of the login.cgi form fields, which differ in casing between firmware
+#[derive(Debug, Clone)]
+pub struct LoginFields {
+    pub username: String,
+    pub password: String,
+    pub language: String,
+    // Also the cookie name with ONT_TOKEN_PLACEMENT=cookie
+    pub token: String,
+}
+
+impl Default for LoginFields {
+    fn default() -> Self {
+        Self {
+            username: "UserName".to_string(),
+            password: "PassWord".to_string(),
+            language: "Language".to_string(),
+            token: "x.X_HW_Token".to_string(),
+        }
+    }
+}
+
+const DEFAULT_LOGOUT_PATH: &str = "/logout.cgi?RequestFile=html/logout.html";
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub ont_url: String,
+    pub ont_url_fallback: Option<String>,
+    pub ont_user: String,
+    pub ont_pass: String,
+    pub login_mode: LoginMode,
+    // How the password is encoded in the login form (cgi mode only)
+    pub password_encoding: PasswordEncoding,
+    // Where the GetRandCount token goes on the login request (cgi mode only)
+    pub token_placement: TokenPlacement,
+    pub login_fields: LoginFields,
+    // Second POST after login.cgi for firmware with a confirm/security-question step.
+    // "{token}" in a form value is replaced with the login token.
+    pub post_login_path: Option<String>,
+    pub post_login_form: Vec<(String, String)>,
+
+    // Logout request, for firmware that doesn't end the session on the default one
+    pub logout_path: String,
+    pub logout_method: reqwest::Method,
+
+    // PEM client certificate and key for mutual TLS
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    // PEM CA certificates trusted for the ONT's server certificate
+    pub ca_bundle: Option<String>,
+
+    // Explicit proxy for ONT requests, overriding the standard *_PROXY variables
+    pub proxy: Option<String>,
+
+    pub scrape_interval: u64,
+    // What the scrape loop does when a scrape overruns the interval
+    pub missed_tick_behavior: MissedTickBehavior,
+    // Start scrapes on wall-clock multiples of the interval (e.g. :00 for 1m) instead of process start
+    pub scrape_align: bool,
+    // Upper bound on a whole scrape (all requests), defaults to the interval
+    pub scrape_deadline: u64,
+
+    // Scrape failures in this many seconds after startup aren't counted as errors
+    pub startup_grace: u64,
+
+    // Log an identical scrape error at most once per this many seconds (0 logs every one)
+    pub log_dedup_secs: u64,
+
+    // Used for the rx headroom metric when the device doesn't report its own threshold
+    pub rx_sensitivity_dbm: Option<f64>,
+    // Known-good rx power captured at install time, for the degradation delta
+    pub rx_power_baseline_dbm: Option<f64>,
+    // Also observe rx power into a histogram, for long-term distribution analysis
+    pub rx_power_histogram: bool,
+    // Number of recent scrapes the rx power slope is fitted over (0 disables it)
+    pub rx_slope_window: usize,
+
+    pub optical: OpticalOptions,
+    pub optical_format: OpticalFormat,
+    // Appended to the optical page URL, {path} stands for the page path (without the leading /)
+    pub optical_query: Option<String>,
+    // Diagnostic command endpoint read when the optical page fails (model-specific, off by default)
+    pub enable_diag: bool,
+    pub diag_path: Option<String>,
+    // Form field carrying the command, and the command itself
+    pub diag_field: String,
+    pub diag_command: String,
+
+    // Optional collectors (optical is always scraped)
+    pub enable_device: bool,
+    pub enable_wan: bool,
+    pub enable_lan: bool,
+    pub enable_wlan: bool,
+
+    // Follow HTTP redirects from the ONT (the login 302 is inspected directly when off)
+    pub follow_redirects: bool,
+
+    // Upper bound on a single response body read from the ONT
+    pub max_body_bytes: usize,
+
+    // Extra attempts per candidate page path when the request itself fails
+    pub path_retries: u32,
+
+    // Minimum seconds between on-demand /probe scrapes of the same target
+    pub probe_min_interval: u64,
+
+    // Persist ONT session cookies here across restarts (and keep the session between scrapes)
+    pub cookie_store_path: Option<String>,
+
+    // Write every fetched page here before parsing (debugging only)
+    pub dump_html_dir: Option<String>,
+    // Bytes of a page that failed to parse to include in the error, 0 = off
+    pub log_html_snippet: usize,
+
+    // Labels kept on huawei_ont_device_info, in DEVICE_INFO_LABELS order
+    pub device_info_labels: Vec<&'static str>,
+    // Longer string label values parsed from the device are truncated to this many bytes
+    pub max_label_len: usize,
+    // How the ip label of huawei_ont_wan_status is exposed
+    pub mask_wan_ip: WanIpMask,
+
+    // Path prefix for every HTTP route, e.g. "/ont" behind a reverse proxy (empty by default)
+    pub route_prefix: String,
+    // Serve /scrape and /paths on this address instead of the main port
+    pub admin_listen_addr: Option<SocketAddr>,
+
+    // Clients allowed to reach the HTTP endpoints (empty allows everyone)
+    pub allowed_ips: Vec<IpNet>,
+    // Take the client address from X-Forwarded-For/Forwarded (only behind a trusted proxy)
+    pub trust_forwarded_for: bool,
+
+    // POST scrape state changes here (standalone setups without Alertmanager)
+    pub alert_webhook_url: Option<String>,
+    // RX power below this fires an alert (webhook only)
+    pub alert_rx_power_dbm: Option<f64>,
+}
+
+impl Config {
+    /// Primary URL followed by the fallback, if any
+    pub fn ont_urls(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.ont_url.as_str()).chain(self.ont_url_fallback.as_deref())
+    }
+
+    pub fn load() -> Self {
+        let scrape_interval = nonzero("SCRAPE_INTERVAL", duration_env("SCRAPE_INTERVAL").unwrap_or(30));
+
+        Self {
+            ont_url: get_env_var("ONT_URL"),
+            ont_url_fallback: optional_env("ONT_URL_FALLBACK"),
+            ont_user: get_secret("ONT_USER"),
+            ont_pass: get_secret("ONT_PASS"),
+            login_mode: match env::var("ONT_LOGIN_MODE").as_deref() {
+                Err(_) | Ok("cgi") => LoginMode::Cgi,
+                Ok("basic") => LoginMode::Basic,
+                Ok(other) => invalid_value("ONT_LOGIN_MODE", other, "cgi, basic"),
+            },
+            password_encoding: match env::var("ONT_AUTH_MODE").as_deref() {
+                Err(_) | Ok("base64") => PasswordEncoding::Base64,
+                Ok("base64url") => PasswordEncoding::Base64Url,
+                Ok("base64url-nopad") => PasswordEncoding::Base64UrlNoPad,
+                Ok(other) => invalid_value("ONT_AUTH_MODE", other, "base64, base64url, base64url-nopad"),
+            },
+            token_placement: match env::var("ONT_TOKEN_PLACEMENT").as_deref() {
+                Err(_) | Ok("form") => TokenPlacement::Form,
+                Ok("cookie") => TokenPlacement::Cookie,
+                Ok(other) => invalid_value("ONT_TOKEN_PLACEMENT", other, "form, cookie"),
+            },
+            login_fields: {
+                let defaults = LoginFields::default();
+                LoginFields {
+                    username: optional_env("ONT_FIELD_USERNAME").unwrap_or(defaults.username),
+                    password: optional_env("ONT_FIELD_PASSWORD").unwrap_or(defaults.password),
+                    language: optional_env("ONT_FIELD_LANGUAGE").unwrap_or(defaults.language),
+                    token: optional_env("ONT_FIELD_TOKEN").unwrap_or(defaults.token),
+                }
+            },
+            post_login_path: optional_env("ONT_POST_LOGIN_PATH"),
+            post_login_form: optional_env("ONT_POST_LOGIN_FORM")
+                .map(|form| parse_form(&form))
+                .unwrap_or_default(),
+            logout_path: optional_env("ONT_LOGOUT_PATH").unwrap_or_else(|| DEFAULT_LOGOUT_PATH.to_string()),
+            logout_method: match env::var("ONT_LOGOUT_METHOD").as_deref() {
+                Err(_) | Ok("GET") | Ok("get") => reqwest::Method::GET,
+                Ok("POST") | Ok("post") => reqwest::Method::POST,
+                Ok(other) => invalid_value("ONT_LOGOUT_METHOD", other, "GET, POST"),
+            },
+            client_cert: optional_env("ONT_CLIENT_CERT"),
+            client_key: optional_env("ONT_CLIENT_KEY"),
+            ca_bundle: optional_env("ONT_CA_BUNDLE"),
+            proxy: optional_env("ONT_PROXY"),
+            scrape_interval,
+            missed_tick_behavior: match env::var("SCRAPE_MISSED_TICK_BEHAVIOR").as_deref() {
+                Err(_) | Ok("skip") => MissedTickBehavior::Skip,
+                Ok("delay") => MissedTickBehavior::Delay,
+                Ok("burst") => MissedTickBehavior::Burst,
+                Ok(other) => invalid_value("SCRAPE_MISSED_TICK_BEHAVIOR", other, "skip, delay, burst"),
+            },
+            scrape_align: env_flag("SCRAPE_ALIGN", false),
+            scrape_deadline: nonzero(
+                "ONT_SCRAPE_DEADLINE_SECS",
+                parse_env("ONT_SCRAPE_DEADLINE_SECS").unwrap_or(scrape_interval),
+            ),
+            startup_grace: parse_env("ONT_STARTUP_GRACE_SECS").unwrap_or(0),
+            log_dedup_secs: parse_env("LOG_DEDUP_SECS").unwrap_or(0),
+            rx_sensitivity_dbm: parse_env("ONT_RX_SENSITIVITY_DBM"),
+            rx_power_baseline_dbm: parse_env("ONT_RX_POWER_BASELINE_DBM"),
+            rx_power_histogram: env_flag("ENABLE_RX_POWER_HISTOGRAM", false),
+            rx_slope_window: parse_env("ONT_RX_SLOPE_WINDOW").unwrap_or(20),
+            optical: OpticalOptions {
+                voltage_scale: parse_env("ONT_VOLTAGE_SCALE").unwrap_or(1.0),
+                bias_scale: parse_env("ONT_BIAS_SCALE").unwrap_or(1.0),
+                power_scale: parse_env("ONT_OPTICAL_POWER_SCALE")
+                    .unwrap_or(if env_flag("ONT_OPTICAL_POWER_RAW", false) { 0.002 } else { 1.0 }),
+                index_overrides: OpticalIndexOverrides {
+                    tx: parse_env("ONT_OPTICAL_INDEX_TX"),
+                    rx: parse_env("ONT_OPTICAL_INDEX_RX"),
+                    voltage: parse_env("ONT_OPTICAL_INDEX_VOLTAGE"),
+                    temperature: parse_env("ONT_OPTICAL_INDEX_TEMPERATURE"),
+                    bias: parse_env("ONT_OPTICAL_INDEX_BIAS"),
+                    tx_nominal: parse_env("ONT_OPTICAL_INDEX_TX_NOMINAL"),
+                    rx_nominal: parse_env("ONT_OPTICAL_INDEX_RX_NOMINAL"),
+                },
+                instance: parse_env("ONT_OPTICAL_INSTANCE"),
+            },
+            optical_format: match env::var("ONT_OPTICAL_FORMAT").as_deref() {
+                Err(_) | Ok("asp") => OpticalFormat::Asp,
+                Ok("json") => OpticalFormat::Json,
+                Ok(other) => invalid_value("ONT_OPTICAL_FORMAT", other, "asp, json"),
+            },
+            optical_query: optional_env("ONT_OPTICAL_QUERY").map(|query| query.trim_start_matches('?').to_string()),
+            enable_diag: env_flag("ENABLE_DIAG", false),
+            diag_path: optional_env("ONT_DIAG_PATH"),
+            diag_field: optional_env("ONT_DIAG_FIELD").unwrap_or_else(|| "cmd".to_string()),
+            diag_command: optional_env("ONT_DIAG_COMMAND").unwrap_or_else(|| "display optic".to_string()),
+            enable_device: env_flag("ENABLE_DEVICE", true),
+            enable_wan: env_flag("ENABLE_WAN", true),
+            enable_lan: env_flag("ENABLE_LAN", true),
+            enable_wlan: env_flag("ENABLE_WLAN", true),
+            follow_redirects: env_flag("ONT_FOLLOW_REDIRECTS", true),
+            max_body_bytes: parse_env("ONT_MAX_BODY_BYTES").unwrap_or(4 * 1024 * 1024),
+            path_retries: parse_env("ONT_PATH_RETRIES").unwrap_or(0),
+            probe_min_interval: parse_env("PROBE_MIN_INTERVAL").unwrap_or(10),
+            cookie_store_path: optional_env("COOKIE_STORE_PATH"),
+            dump_html_dir: optional_env("DUMP_HTML_DIR"),
+            log_html_snippet: parse_env("ONT_LOG_HTML_SNIPPET").unwrap_or(0),
+            device_info_labels: device_info_labels(),
+            max_label_len: parse_env("MAX_LABEL_LEN").unwrap_or(128),
+            mask_wan_ip: match env::var("MASK_WAN_IP").as_deref() {
+                Err(_) | Ok("off") | Ok("false") => WanIpMask::Off,
+                Ok("redact") | Ok("true") => WanIpMask::Redact,
+                Ok("hash") => WanIpMask::Hash { key: wan_ip_salt_key() },
+                Ok(other) => invalid_value("MASK_WAN_IP", other, "off, redact, hash"),
+            },
+            route_prefix: route_prefix(),
+            admin_listen_addr: optional_env("ADMIN_LISTEN_ADDR").map(|addr| {
+                addr.parse()
+                    .unwrap_or_else(|_| invalid_value("ADMIN_LISTEN_ADDR", &addr, "an address such as 127.0.0.1:8001"))
+            }),
+            allowed_ips: allowed_ips(),
+            trust_forwarded_for: env_flag("TRUST_FORWARDED_FOR", false),
+            alert_webhook_url: optional_env("ALERT_WEBHOOK_URL"),
+            alert_rx_power_dbm: parse_env("ALERT_RX_POWER_DBM"),
+        }
+    }
+}
+
+impl Config {
+    /// Problems `load` doesn't catch, for --validate-config. TLS files are
+    /// checked by building a client, see main.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        for (name, url) in [
+            ("ONT_URL", Some(&self.ont_url)),
+            ("ONT_URL_FALLBACK", self.ont_url_fallback.as_ref()),
+            ("ALERT_WEBHOOK_URL", self.alert_webhook_url.as_ref()),
+        ] {
+            let Some(url) = url else { continue };
+            match reqwest::Url::parse(url) {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") => {}
+                Ok(parsed) => problems.push(format!("{}: unsupported scheme {:?} in {}", name, parsed.scheme(), url)),
+                Err(e) => problems.push(format!("{}: invalid URL {:?}: {}", name, url, e)),
+            }
+        }
+
+        if let Some(path) = &self.post_login_path
+            && !path.starts_with('/')
+        {
+            problems.push(format!("ONT_POST_LOGIN_PATH: {:?} must start with /", path));
+        }
+        match &self.diag_path {
+            None if self.enable_diag => problems.push("ENABLE_DIAG: ONT_DIAG_PATH must be set as well".to_string()),
+            Some(path) if !path.starts_with('/') => {
+                problems.push(format!("ONT_DIAG_PATH: {:?} must start with /", path))
+            }
+            _ => {}
+        }
+        if !self.logout_path.starts_with('/') {
+            problems.push(format!("ONT_LOGOUT_PATH: {:?} must start with /", self.logout_path));
+        }
+
+        // Missing is fine (first run), but the directory must exist to save into
+        if let Some(path) = &self.cookie_store_path {
+            let path = std::path::Path::new(path);
+            if path.exists() {
+                if let Err(e) = std::fs::read_to_string(path) {
+                    problems.push(format!("COOKIE_STORE_PATH: cannot read {}: {}", path.display(), e));
+                }
+            } else if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty())
+                && !parent.is_dir()
+            {
+                problems.push(format!("COOKIE_STORE_PATH: directory {} does not exist", parent.display()));
+            }
+        }
+
+        if let Some(dir) = &self.dump_html_dir
+            && !std::path::Path::new(dir).is_dir()
+        {
+            problems.push(format!("DUMP_HTML_DIR: {} is not a directory", dir));
+        }
+
+        problems
+    }
+}
+
+// Comma-separated subset of DEVICE_INFO_LABELS, "version" is short for software_version
+fn device_info_labels() -> Vec<&'static str> {
+    let Some(value) = optional_env("DEVICE_INFO_LABELS") else {
+        return DEVICE_INFO_LABELS.to_vec();
+    };
+
+    let mut wanted = Vec::new();
+    for name in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let name = if name == "version" { "software_version" } else { name };
+        match DEVICE_INFO_LABELS.iter().find(|label| **label == name) {
+            Some(label) => wanted.push(*label),
+            None => invalid_value("DEVICE_INFO_LABELS", name, &DEVICE_INFO_LABELS.join(", ")),
+        }
+    }
+    DEVICE_INFO_LABELS.iter().copied().filter(|label| wanted.contains(label)).collect()
+}
+
+// "ont", "/ont" and "/ont/" all become "/ont"
+fn route_prefix() -> String {
+    match optional_env("ROUTE_PREFIX") {
+        Some(prefix) => {
+            let prefix = prefix.trim().trim_matches('/');
+            if prefix.is_empty() { String::new() } else { format!("/{}", prefix) }
+        }
+        None => String::new(),
+    }
+}
+
+fn allowed_ips() -> Vec<IpNet> {
+    let Some(value) = optional_env("ALLOWED_IPS") else {
+        return Vec::new();
+    };
+    parse_ip_list(&value).unwrap_or_else(|entry| invalid_value("ALLOWED_IPS", &entry, "IP addresses or CIDRs"))
+}
+
+// URL-encoded "name=value&name2=value2", as the form would be posted
+fn parse_form(value: &str) -> Vec<(String, String)> {
+    form_urlencoded::parse(value.trim().as_bytes()).into_owned().collect()
+}
+
+// Comma-separated CIDRs, a bare address is a single host. Err carries the bad entry.
+fn parse_ip_list(value: &str) -> Result<Vec<IpNet>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|entry| {
+            entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<std::net::IpAddr>().map(IpNet::from))
+                .map_err(|_| entry.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+impl Config {
+    /// Defaults as if only ONT_URL, ONT_USER and ONT_PASS were set
+    pub fn for_test(url: &str) -> Self {
+        Self {
+            ont_url: url.to_string(),
+            ont_url_fallback: None,
+            ont_user: "user".to_string(),
+            ont_pass: "pass".to_string(),
+            login_mode: LoginMode::Cgi,
+            password_encoding: PasswordEncoding::Base64,
+            token_placement: TokenPlacement::Form,
+            login_fields: LoginFields::default(),
+            post_login_path: None,
+            post_login_form: Vec::new(),
+            logout_path: DEFAULT_LOGOUT_PATH.to_string(),
+            logout_method: reqwest::Method::GET,
+            client_cert: None,
+            client_key: None,
+            ca_bundle: None,
+            proxy: None,
+            scrape_interval: 30,
+            missed_tick_behavior: MissedTickBehavior::Skip,
+            scrape_align: false,
+            scrape_deadline: 30,
+            startup_grace: 0,
+            log_dedup_secs: 0,
+            rx_sensitivity_dbm: None,
+            rx_power_baseline_dbm: None,
+            rx_power_histogram: false,
+            rx_slope_window: 20,
+            optical: OpticalOptions::default(),
+            optical_format: OpticalFormat::Asp,
+            optical_query: None,
+            enable_diag: false,
+            diag_path: None,
+            diag_field: "cmd".to_string(),
+            diag_command: "display optic".to_string(),
+            enable_device: true,
+            enable_wan: true,
+            enable_lan: true,
+            enable_wlan: true,
+            follow_redirects: true,
+            max_body_bytes: 4 * 1024 * 1024,
+            path_retries: 0,
+            probe_min_interval: 10,
+            cookie_store_path: None,
+            dump_html_dir: None,
+            log_html_snippet: 0,
+            device_info_labels: DEVICE_INFO_LABELS.to_vec(),
+            max_label_len: 128,
+            mask_wan_ip: WanIpMask::Off,
+            route_prefix: String::new(),
+            admin_listen_addr: None,
+            allowed_ips: Vec::new(),
+            trust_forwarded_for: false,
+            alert_webhook_url: None,
+            alert_rx_power_dbm: None,
+        }
+    }
+}
+
+fn get_env_var(name: &str) -> String {
+    env::var(name).unwrap_or_else(|_| {
+        eprintln!("Error: Environment variable {} must be set", name);
+        std::process::exit(1);
+    })
+}
+
+// Prefer `<NAME>_FILE` (Docker/K8s secrets) over the plain env var
+fn get_secret(name: &str) -> String {
+    let file_var = format!("{}_FILE", name);
+    match env::var(&file_var) {
+        Ok(path) => match std::fs::read_to_string(&path) {
+            Ok(contents) => contents.trim_end_matches(['\r', '\n']).to_string(),
+            Err(e) => {
+                eprintln!("Error: Failed to read {} from {}: {}", file_var, path, e);
+                std::process::exit(1);
+            }
+        },
+        Err(_) => get_env_var(name),
+    }
+}
+
+// A secret is what makes the hash irreversible: the IPv4 space is small enough to
+// hash in full, so an unkeyed hash would only look private
+fn wan_ip_salt_key() -> (u64, u64) {
+    use siphasher::sip::SipHasher24;
+    use std::hash::Hasher;
+
+    let salt = optional_env("MASK_WAN_IP_SALT").unwrap_or_default();
+    if salt.len() < 16 {
+        eprintln!("Error: MASK_WAN_IP=hash needs MASK_WAN_IP_SALT set to a random secret of at least 16 characters");
+        std::process::exit(1);
+    }
+    let derive = |k1| {
+        let mut hasher = SipHasher24::new_with_keys(0, k1);
+        hasher.write(salt.as_bytes());
+        hasher.finish()
+    };
+    (derive(0), derive(1))
+}
+
+fn invalid_value(name: &str, value: &str, expected: &str) -> ! {
+    eprintln!("Error: Invalid value {:?} for {}, expected one of: {}", value, name, expected);
+    std::process::exit(1);
+}
+
+// Unset and empty are treated the same
+fn optional_env(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|s| !s.is_empty())
+}
+
+fn parse_env<T: std::str::FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().and_then(|s| s.trim().parse().ok())
+}
+
+// A zero interval would spin the scrape loop and a zero deadline fails every scrape
+fn nonzero(name: &str, value: u64) -> u64 {
+    if value == 0 {
+        eprintln!("Error: Environment variable {} must be greater than 0", name);
+        std::process::exit(1);
+    }
+    value
+}
+
+// Seconds given as a bare number or a duration such as "90s", "2m" or "1h30m"
+fn duration_env(name: &str) -> Option<u64> {
+    let value = optional_env(name)?;
+    match parse_duration_secs(&value) {
+        Some(secs) => Some(secs),
+        None => {
+            eprintln!("Error: Environment variable {} must be a duration (e.g. 30, 90s, 2m), got {:?}", name, value);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn parse_duration_secs(value: &str) -> Option<u64> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(secs);
+    }
+
+    if value.is_empty() {
+        return None;
+    }
+
+    let mut total: u64 = 0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        let number: u64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+        let unit_len = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        let unit = match rest[..unit_len].trim() {
+            "s" | "sec" | "secs" => 1,
+            "m" | "min" | "mins" => 60,
+            "h" | "hr" | "hrs" => 3600,
+            _ => return None,
+        };
+        rest = &rest[unit_len..];
+        total = total.checked_add(number.checked_mul(unit)?)?;
+    }
+    Some(total)
+}
+
+fn env_flag(name: &str, default: bool) -> bool {
+    match env::var(name) {
+        Ok(v) => match v.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => true,
+            "0" | "false" | "no" | "off" => false,
+            _ => {
+                eprintln!("Error: Environment variable {} must be a boolean, got {:?}", name, v);
+                std::process::exit(1);
+            }
+        },
+        Err(_) => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ip_list() {
+        let nets = parse_ip_list("10.0.0.0/8, 192.168.1.5,::1").unwrap();
+        assert_eq!(nets.len(), 3);
+        assert!(nets[0].contains(&"10.1.2.3".parse::<std::net::IpAddr>().unwrap()));
+        assert!(nets[1].contains(&"192.168.1.5".parse::<std::net::IpAddr>().unwrap()));
+        assert!(!nets[1].contains(&"192.168.1.6".parse::<std::net::IpAddr>().unwrap()));
+        assert_eq!(parse_ip_list("10.0.0.0/8,prometheus"), Err("prometheus".to_string()));
+    }
+
+    #[test]
+    fn test_parse_form() {
+        assert_eq!(
+            parse_form("confirm=1&answer=red%20car&x.X_HW_Token={token}"),
+            [
+                ("confirm".to_string(), "1".to_string()),
+                ("answer".to_string(), "red car".to_string()),
+                ("x.X_HW_Token".to_string(), "{token}".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_duration_secs() {
+        assert_eq!(parse_duration_secs("30"), Some(30));
+        assert_eq!(parse_duration_secs("90s"), Some(90));
+        assert_eq!(parse_duration_secs("2m"), Some(120));
+        assert_eq!(parse_duration_secs("1h30m"), Some(5400));
+        assert_eq!(parse_duration_secs("1m 30s"), Some(90));
+        assert_eq!(parse_duration_secs("2x"), None);
+        assert_eq!(parse_duration_secs("m"), None);
+        assert_eq!(parse_duration_secs(""), None);
+    }
+}
@@ -0,0 +1,88 @@
+use std::time::{Duration, Instant};
+
+/// Suppresses repeats of the same scrape error during an outage: an identical
+/// message is logged at most once per window, followed by a "still failing"
+/// summary carrying the repeat count.
+pub struct ErrorDedup {
+    window: Duration,
+    last: Option<Repeat>,
+}
+
+struct Repeat {
+    message: String,
+    logged_at: Instant,
+    // Consecutive occurrences of this message, including the first
+    count: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    Log,
+    // Still failing with the same message, this many times in a row
+    Summary(u64),
+    Suppress,
+}
+
+impl ErrorDedup {
+    /// A zero window logs every failure
+    pub fn new(window: Duration) -> Self {
+        Self { window, last: None }
+    }
+
+    pub fn failure(&mut self, message: &str, now: Instant) -> Decision {
+        if self.window.is_zero() {
+            return Decision::Log;
+        }
+
+        match &mut self.last {
+            Some(repeat) if repeat.message == message => {
+                repeat.count += 1;
+                if now.duration_since(repeat.logged_at) < self.window {
+                    return Decision::Suppress;
+                }
+                repeat.logged_at = now;
+                Decision::Summary(repeat.count)
+            }
+            _ => {
+                self.last = Some(Repeat {
+                    message: message.to_string(),
+                    logged_at: now,
+                    count: 1,
+                });
+                Decision::Log
+            }
+        }
+    }
+
+    /// Forget the current failure, returning how many times in a row it occurred
+    pub fn success(&mut self) -> u64 {
+        self.last.take().map_or(0, |repeat| repeat.count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_dedup() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut dedup = ErrorDedup::new(Duration::from_secs(300));
+
+        assert_eq!(dedup.failure("timeout", at(0)), Decision::Log);
+        assert_eq!(dedup.failure("timeout", at(30)), Decision::Suppress);
+        assert_eq!(dedup.failure("timeout", at(60)), Decision::Suppress);
+        assert_eq!(dedup.failure("timeout", at(300)), Decision::Summary(4));
+        assert_eq!(dedup.failure("timeout", at(330)), Decision::Suppress);
+
+        // A different error is logged right away
+        assert_eq!(dedup.failure("login rejected", at(360)), Decision::Log);
+        assert_eq!(dedup.success(), 1);
+        assert_eq!(dedup.failure("login rejected", at(390)), Decision::Log);
+
+        let mut every = ErrorDedup::new(Duration::ZERO);
+        assert_eq!(every.failure("timeout", at(0)), Decision::Log);
+        assert_eq!(every.failure("timeout", at(1)), Decision::Log);
+    }
+}
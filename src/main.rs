@@ -1,33 +1,72 @@
-use actix_web::{web, App, HttpResponse, HttpServer, Responder};
-use tracing::{debug, error, info};
+// The lazy_static! block of metrics.rs outgrows the default macro recursion limit
+#![recursion_limit = "256"]
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::{from_fn, Next};
+use actix_web::{http::header, web, App, HttpRequest, HttpResponse, HttpServer, Responder};
+use serde::Deserialize;
+use tracing::{debug, error, info, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use prometheus::{Encoder, TextEncoder};
-use std::env;
-use std::time::{Duration, Instant};
+use prometheus::{Encoder, IntGauge, TextEncoder};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::time;
+use anyhow::Context;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Write;
 
 mod client;
+mod config;
+mod log_dedup;
 mod metrics;
+mod notify;
 mod parser;
+mod probe;
 
-use client::OntClient;
+use client::{error_reason, is_auth_error, is_connect_error, OntClient, PathCache, SessionStore};
+use config::Config;
+use log_dedup::{Decision, ErrorDedup};
 use metrics::{
-    update_metrics, HTTP_REQUESTS_ERRORS, HTTP_REQUESTS_TOTAL, SCRAPE_DURATION, SCRAPE_ERRORS,
-    SCRAPES_TOTAL,
+    ACTIVE_URL, EXPORTER_UP, HTTP_REQUESTS_ERRORS, HTTP_REQUESTS_TOTAL, OPENMETRICS_FORMAT,
+    RX_POWER_SLOPE, RxPowerTrend, SCRAPE_DURATION, SCRAPE_ERRORS, SCRAPE_IN_PROGRESS,
+    SCRAPE_MISSED_TICKS, SCRAPES_TOTAL, WAN_IP_CHANGED, WanIpTracker, encode_openmetrics,
+    encode_plain, gather, has_scraped, latest_metrics, mask_wan_ips, record_probe,
+    register_device_info, register_freshness_collector, register_process_collector, update_metrics,
 };
+use notify::Notifier;
+use parser::OntMetrics;
+use probe::{ProbeGuard, ProbeRejection};
 
-fn get_env_var(name: &str) -> String {
-    env::var(name).unwrap_or_else(|_| {
-        eprintln!("Error: Environment variable {} must be set", name);
-        std::process::exit(1);
-    })
+// Shared between the HTTP handlers
+struct AppState {
+    config: Config,
+    path_cache: PathCache,
+    session: Option<SessionStore>,
+    probe_guard: ProbeGuard,
 }
 
-async fn metrics_handler() -> impl Responder {
+async fn metrics_handler(req: HttpRequest) -> impl Responder {
     HTTP_REQUESTS_TOTAL.inc();
+    render_metrics(&req)
+}
 
+fn render_metrics(req: &HttpRequest) -> HttpResponse {
     let encoder = TextEncoder::new();
-    let metric_families = prometheus::gather();
+    let metric_families = gather();
+
+    if wants_openmetrics(req) {
+        return match encode_openmetrics(&metric_families) {
+            Ok(s) => metrics_response(req, OPENMETRICS_FORMAT, s.into_bytes()),
+            Err(e) => {
+                error!("Failed to encode metrics: {}", e);
+                HTTP_REQUESTS_ERRORS.inc();
+                HttpResponse::InternalServerError().body("Failed to encode metrics")
+            }
+        };
+    }
+
     let mut buffer = vec![];
     if let Err(e) = encoder.encode(&metric_families, &mut buffer) {
         error!("Failed to encode metrics: {}", e);
@@ -36,7 +75,7 @@ async fn metrics_handler() -> impl Responder {
     }
 
     match String::from_utf8(buffer) {
-        Ok(s) => HttpResponse::Ok().content_type("text/plain").body(s),
+        Ok(s) => metrics_response(req, "text/plain", s.into_bytes()),
         Err(e) => {
             error!("Failed to convert metrics buffer to string: {}", e);
             HTTP_REQUESTS_ERRORS.inc();
@@ -45,11 +84,380 @@ async fn metrics_handler() -> impl Responder {
     }
 }
 
+// Below this the gzip header and CPU time outweigh the saving
+const GZIP_MIN_BYTES: usize = 1024;
+
+// Gzip the encoded metrics for clients that accept it, as Prometheus does
+fn metrics_response(req: &HttpRequest, content_type: &str, body: Vec<u8>) -> HttpResponse {
+    let mut response = HttpResponse::Ok();
+    response.content_type(content_type).insert_header((header::VARY, "Accept-Encoding"));
+    if body.len() < GZIP_MIN_BYTES || !accepts_gzip(req) {
+        return response.body(body);
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    match encoder.write_all(&body).and_then(|()| encoder.finish()) {
+        Ok(compressed) => response.insert_header((header::CONTENT_ENCODING, "gzip")).body(compressed),
+        Err(e) => {
+            warn!("Failed to gzip metrics, sending them uncompressed: {}", e);
+            response.body(body)
+        }
+    }
+}
+
+// Honors an explicit gzip;q=0 refusal
+fn accepts_gzip(req: &HttpRequest) -> bool {
+    let Some(accept) = req.headers().get(header::ACCEPT_ENCODING).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+    accept.split(',').any(|coding| {
+        let mut parts = coding.split(';').map(str::trim);
+        let name = parts.next().unwrap_or("");
+        let refused = parts.any(|param| {
+            param.strip_prefix("q=").and_then(|q| q.trim().parse::<f32>().ok()) == Some(0.0)
+        });
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !refused
+    })
+}
+
+fn wants_openmetrics(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/openmetrics-text"))
+}
+
+// The last scrape as flat `name value` lines
+async fn plain_metrics_handler(state: web::Data<AppState>) -> impl Responder {
+    HTTP_REQUESTS_TOTAL.inc();
+    match latest_metrics(&state.config.ont_url) {
+        Some(data) => HttpResponse::Ok().content_type("text/plain").body(encode_plain(&data)),
+        None => {
+            HTTP_REQUESTS_ERRORS.inc();
+            HttpResponse::ServiceUnavailable().body("No successful scrape yet")
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ProbeQuery {
+    target: Option<String>,
+}
+
+// On-demand scrape. Only the configured ONT URLs may be probed since the
+// configured credentials are sent to the target.
+async fn probe_handler(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+    query: web::Query<ProbeQuery>,
+) -> impl Responder {
+    HTTP_REQUESTS_TOTAL.inc();
+    let config = &state.config;
+
+    let urls: Vec<&str> = match query.target.as_deref() {
+        Some(target) if config.ont_urls().any(|url| url == target) => vec![target],
+        Some(target) => {
+            HTTP_REQUESTS_ERRORS.inc();
+            return HttpResponse::BadRequest().body(format!("Unknown probe target: {}", target));
+        }
+        None => config.ont_urls().collect(),
+    };
+
+    let _permit = match state.probe_guard.try_acquire(urls[0]) {
+        Ok(permit) => permit,
+        Err(rejection) => {
+            HTTP_REQUESTS_ERRORS.inc();
+            let (retry_after, reason) = match rejection {
+                ProbeRejection::InFlight => (1, "Probe already in progress".to_string()),
+                ProbeRejection::TooSoon(wait) => (
+                    wait.as_secs().max(1),
+                    format!("Probe rate limited, retry in {}s", wait.as_secs().max(1)),
+                ),
+            };
+            return HttpResponse::TooManyRequests()
+                .insert_header(("Retry-After", retry_after.to_string()))
+                .body(reason);
+        }
+    };
+
+    // The probe metrics are rendered whether or not the ONT produced any data
+    let start = Instant::now();
+    let result = scrape_and_record(config, &urls, &state.path_cache, state.session.as_ref(), None).await;
+    record_probe(&config.ont_url, start.elapsed(), result.as_ref().err().map(error_reason));
+    render_metrics(&req)
+}
+
+// Immediate scrape of the configured ONT, returning what was scraped
+async fn scrape_handler(state: web::Data<AppState>) -> impl Responder {
+    HTTP_REQUESTS_TOTAL.inc();
+    let urls: Vec<&str> = state.config.ont_urls().collect();
+
+    match scrape_and_record(&state.config, &urls, &state.path_cache, state.session.as_ref(), None).await {
+        Ok(metrics) => HttpResponse::Ok().json(metrics),
+        Err(e) => {
+            HTTP_REQUESTS_ERRORS.inc();
+            HttpResponse::BadGateway().json(serde_json::json!({ "error": format!("{:#}", e) }))
+        }
+    }
+}
+
+// Which candidate path serves each optional page, and what the others returned
+async fn paths_handler(state: web::Data<AppState>) -> impl Responder {
+    HTTP_REQUESTS_TOTAL.inc();
+    HttpResponse::Ok().json(state.path_cache.report())
+}
+
 async fn health_handler() -> impl Responder {
     HTTP_REQUESTS_TOTAL.inc();
     HttpResponse::Ok().body("OK")
 }
 
+// Parses the bundled sample page, so a deployment can be checked without an ONT
+async fn selftest_handler() -> impl Responder {
+    HTTP_REQUESTS_TOTAL.inc();
+    match parser::self_test() {
+        Ok(metrics) => HttpResponse::Ok().json(metrics),
+        Err(e) => {
+            HTTP_REQUESTS_ERRORS.inc();
+            HttpResponse::InternalServerError().json(serde_json::json!({ "error": format!("{:#}", e) }))
+        }
+    }
+}
+
+// Readiness, unlike /health: only once there are ONT metrics to serve
+async fn ready_handler() -> impl Responder {
+    HTTP_REQUESTS_TOTAL.inc();
+    if has_scraped() {
+        HttpResponse::Ok().body("Ready")
+    } else {
+        HttpResponse::ServiceUnavailable().body("No successful scrape yet")
+    }
+}
+
+// ALLOWED_IPS: 403 for clients outside the list. The forwarded address is only
+// used with TRUST_FORWARDED_FOR, otherwise any client could claim an allowed one.
+async fn ip_allowlist(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let state = req.app_data::<web::Data<AppState>>().expect("AppState is registered").clone();
+    let allowed = &state.config.allowed_ips;
+    if allowed.is_empty() {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+
+    let client_ip = if state.config.trust_forwarded_for {
+        req.connection_info().realip_remote_addr().and_then(parse_client_ip)
+    } else {
+        req.peer_addr().map(|addr| addr.ip())
+    };
+
+    if client_ip.is_some_and(|ip| allowed.iter().any(|net| net.contains(&ip))) {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+
+    warn!("Rejected request to {} from {:?}", req.path(), client_ip);
+    HTTP_REQUESTS_ERRORS.inc();
+    Ok(req.into_response(HttpResponse::Forbidden().body("Forbidden")).map_into_right_body())
+}
+
+// Forwarded addresses come as "1.2.3.4", "1.2.3.4:5678" or "[::1]:5678"
+fn parse_client_ip(addr: &str) -> Option<std::net::IpAddr> {
+    addr.parse()
+        .ok()
+        .or_else(|| addr.parse::<std::net::SocketAddr>().ok().map(|a| a.ip()))
+}
+
+// Holds huawei_ont_scrape_in_progress up, also if the scrape future is dropped
+struct InProgress(IntGauge);
+
+impl InProgress {
+    fn start(target: &str) -> Self {
+        let gauge = SCRAPE_IN_PROGRESS.with_label_values(&[target]);
+        gauge.inc();
+        InProgress(gauge)
+    }
+}
+
+impl Drop for InProgress {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+static SCRAPE_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+// For ONT_STARTUP_GRACE_SECS
+static STARTED_AT: OnceLock<Instant> = OnceLock::new();
+
+fn in_startup_grace(config: &Config) -> bool {
+    STARTED_AT
+        .get()
+        .is_some_and(|started| started.elapsed() < Duration::from_secs(config.startup_grace))
+}
+
+// For LOG_DEDUP_SECS, so an outage doesn't log the same error every interval
+static SCRAPE_ERROR_LOG: OnceLock<Mutex<ErrorDedup>> = OnceLock::new();
+
+fn log_scrape_failure(e: &anyhow::Error) {
+    let message = format!("{:#}", e);
+    let decision = match SCRAPE_ERROR_LOG.get() {
+        Some(dedup) => dedup.lock().unwrap().failure(&message, Instant::now()),
+        None => Decision::Log,
+    };
+    match decision {
+        Decision::Log => error!("Scrape failed: {}", message),
+        Decision::Summary(count) => error!("Scrape still failing ({} times in a row): {}", count, message),
+        Decision::Suppress => debug!("Scrape failed: {}", message),
+    }
+}
+
+// Run one scrape and record its outcome in the scrape metrics
+async fn scrape_and_record(
+    config: &Config,
+    urls: &[&str],
+    path_cache: &PathCache,
+    session: Option<&SessionStore>,
+    wan_ip: Option<&mut WanIpTracker>,
+) -> anyhow::Result<OntMetrics> {
+    // Periodic, /probe and /scrape runs never log in to the ONT concurrently
+    let _lock = SCRAPE_LOCK.lock().await;
+
+    let target = config.ont_url.as_str();
+    SCRAPES_TOTAL.with_label_values(&[target]).inc();
+    let start = Instant::now();
+
+    let in_progress = InProgress::start(target);
+    let deadline = Duration::from_secs(config.scrape_deadline);
+    let result = time::timeout(deadline, scrape_with_failover(config, urls, path_cache, session))
+        .await
+        .unwrap_or_else(|elapsed| {
+            Err(anyhow::Error::new(elapsed).context(format!("Scrape exceeded deadline of {}s", config.scrape_deadline)))
+        });
+    drop(in_progress);
+
+    match result {
+        Ok(mut metrics) => {
+            // Compared before masking, which would hide a change under MASK_WAN_IP=redact.
+            // Created on the first address, so the counter reads 0 rather than being absent.
+            if let Some(tracker) = wan_ip
+                && let Some(changed) = tracker.observe(metrics.wan_ip.as_deref())
+            {
+                let counter = WAN_IP_CHANGED.with_label_values(&[target]);
+                if changed {
+                    counter.inc();
+                    info!("WAN IP changed");
+                }
+            }
+            // Masked here so /scrape, the logs and the metrics never see the raw address
+            mask_wan_ips(&mut metrics, config.mask_wan_ip);
+
+            let duration = start.elapsed().as_secs_f64();
+            SCRAPE_DURATION.with_label_values(&[target]).observe(duration);
+            debug!("Scrape successful: {:?}", metrics);
+            if let Some(dedup) = SCRAPE_ERROR_LOG.get() {
+                let failures = dedup.lock().unwrap().success();
+                if failures > 1 {
+                    info!("Scrape recovered after {} consecutive failures", failures);
+                }
+            }
+            update_metrics(target, &metrics, config);
+            Ok(metrics)
+        }
+        Err(e) => {
+            // The ONT may still be booting right after a restart of both
+            if in_startup_grace(config) {
+                warn!("Scrape failed during startup grace period: {:#}", e);
+            } else {
+                SCRAPE_ERRORS.with_label_values(&[target, error_reason(&e)]).inc();
+                log_scrape_failure(&e);
+            }
+            Err(e)
+        }
+    }
+}
+
+// Scrape the first URL, moving on to the next one only if the device can't be reached
+async fn scrape_with_failover(
+    config: &Config,
+    urls: &[&str],
+    path_cache: &PathCache,
+    session: Option<&SessionStore>,
+) -> anyhow::Result<OntMetrics> {
+    let mut last_err = None;
+    for url in urls {
+        // Create a new client for each scrape to ensure fresh session state
+        // (unless COOKIE_STORE_PATH asks for the session to be kept)
+        let client = OntClient::new(config, url, path_cache.clone(), session)
+            .context("Failed to create ONT client")?;
+        match client.scrape_metrics().await {
+            Ok(metrics) => {
+                for candidate in urls {
+                    ACTIVE_URL.with_label_values(&[config.ont_url.as_str(), candidate]).set((candidate == url) as i64);
+                }
+                return Ok(metrics);
+            }
+            Err(e) if is_connect_error(&e) => {
+                if urls.len() > 1 {
+                    warn!("ONT unreachable at {}: {:#}", url, e);
+                }
+                last_err = Some(e);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(last_err.expect("at least one URL is always configured"))
+}
+
+// --validate-config: check the configuration without contacting the ONT, then exit
+fn validate_config(config: &Config) -> ! {
+    let mut problems = config.validate();
+
+    // Building a client loads the TLS files and checks ONT_PROXY
+    if let Err(e) = OntClient::new(config, &config.ont_url, PathCache::default(), None) {
+        problems.push(format!("{:#}", e));
+    }
+
+    if problems.is_empty() {
+        println!("Configuration OK");
+        std::process::exit(0);
+    }
+    for problem in &problems {
+        eprintln!("Error: {}", problem);
+    }
+    eprintln!("Configuration invalid ({} problem(s))", problems.len());
+    std::process::exit(1);
+}
+
+// Time until the next multiple of `period` since the Unix epoch, zero when exactly on one
+fn delay_to_boundary(now: SystemTime, period: Duration) -> Duration {
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    let period = period.as_millis().max(1);
+    let remainder = since_epoch % period;
+    if remainder == 0 {
+        Duration::ZERO
+    } else {
+        Duration::from_millis((period - remainder) as u64)
+    }
+}
+
+// What Prometheus and health checks need, always on the main port
+fn scrape_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/metrics", web::get().to(metrics_handler))
+        .route("/health", web::get().to(health_handler))
+        .route("/ready", web::get().to(ready_handler));
+}
+
+// Debugging, self-test and on-demand scrapes, moved to ADMIN_LISTEN_ADDR when it is set
+fn admin_routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/metrics/plain", web::get().to(plain_metrics_handler))
+        .route("/probe", web::get().to(probe_handler))
+        .route("/selftest", web::get().to(selftest_handler))
+        .route("/scrape", web::post().to(scrape_handler))
+        .route("/paths", web::get().to(paths_handler));
+}
+
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
     let env_filter = tracing_subscriber::EnvFilter::try_from_default_env()
@@ -60,64 +468,158 @@ async fn main() -> std::io::Result<()> {
         .with(tracing_subscriber::fmt::layer().json())
         .init();
 
-    let ont_url = get_env_var("ONT_URL");
-    let ont_user = get_env_var("ONT_USER");
-    let ont_pass = get_env_var("ONT_PASS");
-    let scrape_interval = env::var("SCRAPE_INTERVAL")
-        .ok()
-        .and_then(|s| s.parse().ok())
-        .unwrap_or(30);
+    STARTED_AT.get_or_init(Instant::now);
+    let config = Config::load();
+    SCRAPE_ERROR_LOG.get_or_init(|| Mutex::new(ErrorDedup::new(Duration::from_secs(config.log_dedup_secs))));
+
+    if std::env::args().any(|arg| arg == "--validate-config") {
+        validate_config(&config);
+    }
+
+    register_process_collector();
+    register_device_info(&config.device_info_labels);
+    register_freshness_collector();
+    EXPORTER_UP.set(1);
 
     info!("Starting ONT Metrics Scraper");
-    info!("Target URL: {}", ont_url);
-    info!("Scrape Interval: {}s", scrape_interval);
+    info!("Target URL: {}", config.ont_url);
+    if let Some(fallback) = &config.ont_url_fallback {
+        info!("Fallback URL: {}", fallback);
+    }
+    info!("Scrape Interval: {}s", config.scrape_interval);
 
-    // Spawn background scraping task
-    let url = ont_url.clone();
-    let user = ont_user.clone();
-    let pass = ont_pass.clone();
+    let path_cache = PathCache::default();
+    let session = config
+        .cookie_store_path
+        .as_deref()
+        .map(|path| SessionStore::load(path, config.ont_urls()));
+    let probe_min_interval = Duration::from_secs(config.probe_min_interval);
 
+    // Validate credentials once before entering the loop; only auth failures are fatal
+    match OntClient::new(&config, &config.ont_url, path_cache.clone(), session.as_ref()) {
+        Ok(client) => match client.check_login().await {
+            Ok(()) => info!("Startup login check passed"),
+            Err(e) if is_auth_error(&e) => {
+                error!("Fatal: ONT rejected the configured credentials, check ONT_USER/ONT_PASS: {:#}", e);
+                std::process::exit(1);
+            }
+            Err(e) => warn!("Startup login check failed, continuing anyway: {:#}", e),
+        },
+        Err(e) => warn!("Failed to create ONT client for startup check: {}", e),
+    }
+
+    let state = web::Data::new(AppState {
+        config: config.clone(),
+        path_cache: path_cache.clone(),
+        session: session.clone(),
+        probe_guard: ProbeGuard::new(probe_min_interval),
+    });
+
+    // Spawn background scraping task
+    let shutdown_state = state.clone();
     tokio::spawn(async move {
-        let mut interval = time::interval(Duration::from_secs(scrape_interval));
+        let urls: Vec<&str> = config.ont_urls().collect();
+        let mut pages_logged = false;
+        let period = Duration::from_secs(config.scrape_interval);
+        let mut interval = if config.scrape_align {
+            let delay = delay_to_boundary(SystemTime::now(), period);
+            info!("Aligning scrapes to the interval, first scrape in {:.1}s", delay.as_secs_f64());
+            time::interval_at(time::Instant::now() + delay, period)
+        } else {
+            time::interval(period)
+        };
+        interval.set_missed_tick_behavior(config.missed_tick_behavior);
+        let mut last_tick = None;
+        let mut rx_trend = RxPowerTrend::new(config.rx_slope_window);
+        let mut wan_ip = WanIpTracker::default();
+        let mut notifier = config
+            .alert_webhook_url
+            .as_deref()
+            .map(|url| Notifier::new(url, &config.ont_url, config.alert_rx_power_dbm));
         loop {
-            interval.tick().await;
-            debug!("Scraping metrics...");
+            let tick = interval.tick().await;
 
-            SCRAPES_TOTAL.inc();
-            let start = Instant::now();
-
-            // Create a new client for each scrape to ensure fresh session state
-            match OntClient::new(&url, &user, &pass) {
-                Ok(client) => {
-                    match client.scrape_metrics().await {
-                        Ok(metrics) => {
-                            let duration = start.elapsed().as_secs_f64();
-                            SCRAPE_DURATION.observe(duration);
-                            debug!("Scrape successful: {:?}", metrics);
-                            update_metrics(&metrics);
-                        }
-                        Err(e) => {
-                            SCRAPE_ERRORS.inc();
-                            error!("Scrape failed: {:#}", e);
-                        }
-                    }
+            // Whole intervals that passed without a scrape starting
+            if let Some(last) = last_tick {
+                let missed = (tick.duration_since(last).as_nanos() / period.as_nanos()).saturating_sub(1) as u64;
+                if missed > 0 {
+                    SCRAPE_MISSED_TICKS.with_label_values(&[&config.ont_url]).inc_by(missed);
+                    warn!("Scrape loop fell behind, skipped {} interval(s)", missed);
                 }
-                Err(e) => {
-                    SCRAPE_ERRORS.inc();
-                    error!("Failed to create ONT client: {}", e);
+            }
+            last_tick = Some(tick);
+
+            debug!("Scraping metrics...");
+            let result = scrape_and_record(&config, &urls, &path_cache, session.as_ref(), Some(&mut wan_ip)).await;
+            let ok = result.is_ok();
+            if let Some(notifier) = &mut notifier {
+                notifier.observe(&result, in_startup_grace(&config));
+            }
+
+            // Only the periodic scrapes feed the trend, so samples are evenly spaced
+            if let Ok(metrics) = &result
+                && metrics.optical_present
+                && !metrics.rx_power.is_nan()
+            {
+                rx_trend.record(Instant::now(), metrics.rx_power);
+                if let Some(slope) = rx_trend.slope_db_per_hour() {
+                    RX_POWER_SLOPE.with_label_values(&[&config.ont_url]).set(slope);
                 }
             }
+
+            // Log once which optional pages this model serves, to help map firmware variants
+            if ok && !pages_logged {
+                info!("Optional pages found: {}", path_cache.summary());
+                pages_logged = true;
+            }
         }
     });
 
     info!("Starting HTTP server on 0.0.0.0:8000");
-    HttpServer::new(|| {
+    let route_prefix = state.config.route_prefix.clone();
+    if !route_prefix.is_empty() {
+        info!("Serving under route prefix {}", route_prefix);
+    }
+    let admin_addr = state.config.admin_listen_addr;
+    let main_state = state.clone();
+    let main_prefix = route_prefix.clone();
+    let main_server = HttpServer::new(move || {
         App::new()
-            .route("/metrics", web::get().to(metrics_handler))
-            .route("/health", web::get().to(health_handler))
+            .app_data(main_state.clone())
+            .wrap(from_fn(ip_allowlist))
+            .service(web::scope(&main_prefix).configure(|cfg| {
+                scrape_routes(cfg);
+                if admin_addr.is_none() {
+                    admin_routes(cfg);
+                }
+            }))
     })
     .workers(2)
     .bind("0.0.0.0:8000")?
-    .run()
-    .await
+    .run();
+
+    match admin_addr {
+        Some(addr) => {
+            info!("Starting admin HTTP server on {}", addr);
+            let admin_server = HttpServer::new(move || {
+                App::new()
+                    .app_data(state.clone())
+                    .wrap(from_fn(ip_allowlist))
+                    .service(web::scope(&route_prefix).configure(admin_routes))
+            })
+            .workers(1)
+            .bind(addr)?
+            .run();
+            tokio::try_join!(main_server, admin_server)?;
+        }
+        None => main_server.await?,
+    }
+
+    if let Some(session) = &shutdown_state.session {
+        match session.save(shutdown_state.config.ont_urls()) {
+            Ok(()) => info!("Saved ONT session cookies"),
+            Err(e) => error!("{:#}", e),
+        }
+    }
+    Ok(())
 }
@@ -1,100 +1,415 @@
-use crate::parser::OntMetrics;
+use crate::config::{Config, WanIpMask};
+use crate::parser::{OntMetrics, WanConnection};
 use lazy_static::lazy_static;
+use prometheus::core::{Collector, Desc, MetricVec, MetricVecBuilder};
 use prometheus::{
-    register_counter, register_gauge, register_histogram, register_int_gauge_vec, Counter, Gauge,
-    Histogram, IntGaugeVec, Opts,
+    register_counter, register_counter_vec, register_gauge_vec, register_histogram_vec,
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Counter, CounterVec, GaugeVec, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
 };
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::sync::{Mutex, OnceLock, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use siphasher::sip::SipHasher24;
+use std::hash::Hasher;
+use tracing::{debug, warn};
 
+// Each metric is registered on first use, so the ONT gauges only appear once a
+// scrape has set them instead of reading 0 before the first successful scrape.
+// Don't touch them (or lazy_static::initialize them) at startup.
+//
+// Everything about an ONT carries a leading `target` label (its ONT_URL) so several
+// ONTs can share the registry; only the exporter's own HTTP metrics are unlabeled.
+// Clear a target's series with reset_target, never reset(), which drops every target.
 lazy_static! {
     // ONT Optical Metrics
-    pub static ref TX_POWER: Gauge = register_gauge!(
+    pub static ref TX_POWER: GaugeVec = register_gauge_vec!(
         "huawei_ont_optical_tx_power_dbm",
-        "Transmit optical power in dBm"
+        "Transmit optical power in dBm",
+        &["target", "port"]
     )
     .expect("metric registration failed");
-    pub static ref RX_POWER: Gauge = register_gauge!(
+    pub static ref RX_POWER: GaugeVec = register_gauge_vec!(
         "huawei_ont_optical_rx_power_dbm",
-        "Receive optical power in dBm"
+        "Receive optical power in dBm",
+        &["target", "port"]
     )
     .expect("metric registration failed");
-    pub static ref VOLTAGE: Gauge =
-        register_gauge!("huawei_ont_working_voltage_mv", "Working voltage in mV")
+    pub static ref TX_POWER_NOMINAL: GaugeVec = register_gauge_vec!(
+        "huawei_ont_optical_tx_power_nominal_dbm",
+        "Nominal transmit optical power in dBm (when reported)",
+        &["target", "port"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref RX_POWER_NOMINAL: GaugeVec = register_gauge_vec!(
+        "huawei_ont_optical_rx_power_nominal_dbm",
+        "Nominal receive optical power in dBm (when reported)",
+        &["target", "port"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref VOLTAGE: GaugeVec =
+        register_gauge_vec!("huawei_ont_working_voltage_mv", "Working voltage in mV", &["target", "port"])
             .expect("metric registration failed");
-    pub static ref BIAS_CURRENT: Gauge =
-        register_gauge!("huawei_ont_bias_current_ma", "Bias current in mA")
+    pub static ref BIAS_CURRENT: GaugeVec =
+        register_gauge_vec!("huawei_ont_bias_current_ma", "Bias current in mA", &["target", "port"])
             .expect("metric registration failed");
-    pub static ref TEMPERATURE: Gauge = register_gauge!(
+    pub static ref TEMPERATURE: GaugeVec = register_gauge_vec!(
         "huawei_ont_working_temperature_celsius",
-        "Working temperature in Celsius"
+        "Working temperature in Celsius",
+        &["target", "port"]
+    )
+    .expect("metric registration failed");
+    pub static ref RX_HEADROOM: GaugeVec = register_gauge_vec!(
+        "huawei_ont_optical_rx_headroom_db",
+        "Receive power margin above the receiver sensitivity floor in dB",
+        &["target"]
+    )
+    .expect("metric registration failed");
+    pub static ref RX_POWER_DELTA: GaugeVec = register_gauge_vec!(
+        "huawei_ont_optical_rx_power_delta_db",
+        "Receive power relative to the configured baseline in dB",
+        &["target"]
+    )
+    .expect("metric registration failed");
+    // GPON class B+/C+ receivers bottom out around -27 to -30 dBm, so the buckets are densest there
+    pub static ref RX_POWER_HISTOGRAM: HistogramVec = register_histogram_vec!(
+        "huawei_ont_optical_rx_power_dbm_hist",
+        "Distribution of scraped receive optical power in dBm",
+        &["target"],
+        vec![-32.0, -30.0, -29.0, -28.0, -27.0, -26.0, -25.0, -24.0, -23.0, -22.0, -20.0, -18.0, -15.0, -10.0, -8.0]
+    )
+    .expect("metric registration failed");
+    pub static ref RX_POWER_SLOPE: GaugeVec = register_gauge_vec!(
+        "huawei_ont_optical_rx_power_slope_db_per_hour",
+        "Linear-fit trend of receive power over the recent scrape window in dB per hour",
+        &["target"]
     )
     .expect("metric registration failed");
 
-    // Device Info Metrics (using labels - always value 1)
-    pub static ref DEVICE_INFO: IntGaugeVec = register_int_gauge_vec!(
-        Opts::new("huawei_ont_device_info", "Device information (always 1)"),
-        &["model", "serial", "hardware_version", "software_version", "mac_address"]
+    pub static ref OPTICAL_FIELD_COUNT: IntGaugeVec = register_int_gauge_vec!(
+        "huawei_ont_optical_field_count",
+        "Number of arguments in the stOpticInfo call of the optical page",
+        &["target"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref OPTICAL_PRESENT: IntGaugeVec = register_int_gauge_vec!(
+        "huawei_ont_optical_present",
+        "Whether the optical page reported transceiver data (0 when the link is down)",
+        &["target"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref LASER_ALARM: GaugeVec = register_gauge_vec!(
+        "huawei_ont_optical_laser_alarm",
+        "Rogue ONT / laser-always-on alarm (1=alarm, 0=normal)",
+        &["target"]
     )
     .expect("metric registration failed");
 
-    pub static ref UPTIME: Gauge = register_gauge!(
+    pub static ref OPTICAL_LOS: GaugeVec = register_gauge_vec!(
+        "huawei_ont_optical_los",
+        "GPON loss-of-signal alarm (1=alarm, 0=normal)",
+        &["target"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref OPTICAL_LOF: GaugeVec = register_gauge_vec!(
+        "huawei_ont_optical_lof",
+        "GPON loss-of-frame alarm (1=alarm, 0=normal)",
+        &["target"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref GPON_REGISTRATION_INFO: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "huawei_ont_gpon_registration_info",
+            "GPON registration identity (1=registered with an ONU ID, 0=not registered)"
+        ),
+        &["target", "onu_id", "loid"]
+    )
+    .expect("metric registration failed");
+
+    // GPON Error Counters
+    pub static ref GPON_FEC_CORRECTED: IntCounterVec = register_int_counter_vec!(
+        "huawei_ont_gpon_fec_corrected_total",
+        "Total FEC corrected codewords reported by the device",
+        &["target"]
+    )
+    .expect("metric registration failed");
+    pub static ref GPON_BIP_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "huawei_ont_gpon_bip_errors_total",
+        "Total BIP errors reported by the device",
+        &["target"]
+    )
+    .expect("metric registration failed");
+
+    // Last raw device value per cumulative counter, used to apply deltas
+    static ref LAST_COUNTER_VALUES: Mutex<HashMap<(String, &'static str), u64>> = Mutex::new(HashMap::new());
+
+    // Device Info Metrics (using labels - always value 1)
+    pub static ref TIME_SYNCED: IntGaugeVec = register_int_gauge_vec!(
+        "huawei_ont_time_synced",
+        "Whether the device clock is synchronized via NTP (1=synced, 0=not synced)",
+        &["target"]
+    )
+    .expect("metric registration failed");
+    pub static ref DEVICE_TIME: GaugeVec = register_gauge_vec!(
+        "huawei_ont_device_time_seconds",
+        "Current device clock as a Unix timestamp (local time read as UTC unless the device gives an offset)",
+        &["target"]
+    )
+    .expect("metric registration failed");
+    pub static ref UPTIME: GaugeVec = register_gauge_vec!(
         "huawei_ont_uptime_seconds",
-        "Device uptime in seconds"
+        "Device uptime in seconds",
+        &["target"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref CPU_USAGE: GaugeVec = register_gauge_vec!(
+        "huawei_ont_cpu_usage_percent",
+        "Device CPU utilization in percent",
+        &["target"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref MEMORY_USAGE: GaugeVec = register_gauge_vec!(
+        "huawei_ont_memory_usage_percent",
+        "Device memory usage in percent",
+        &["target"]
     )
     .expect("metric registration failed");
 
     // WAN Metrics
     pub static ref WAN_STATUS: IntGaugeVec = register_int_gauge_vec!(
         Opts::new("huawei_ont_wan_status", "WAN connection status (1=up, 0=down)"),
-        &["ip"]
+        &["target", "name", "service", "ip"]
     )
     .expect("metric registration failed");
 
-    pub static ref WAN_RX_BYTES: Gauge = register_gauge!(
+    pub static ref WAN_RX_BYTES: GaugeVec = register_gauge_vec!(
         "huawei_ont_wan_rx_bytes",
-        "Total WAN bytes received"
+        "Total WAN bytes received",
+        &["target", "name"]
     )
     .expect("metric registration failed");
 
-    pub static ref WAN_TX_BYTES: Gauge = register_gauge!(
+    pub static ref WAN_TX_BYTES: GaugeVec = register_gauge_vec!(
         "huawei_ont_wan_tx_bytes",
-        "Total WAN bytes transmitted"
+        "Total WAN bytes transmitted",
+        &["target", "name"]
+    )
+    .expect("metric registration failed");
+    pub static ref WAN_LAST_ERROR_INFO: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "huawei_ont_wan_last_error_info",
+            "Last connection error the ONT recorded for each WAN connection (always 1)"
+        ),
+        &["target", "name", "error"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref WAN_IP_CHANGED: IntCounterVec = register_int_counter_vec!(
+        "huawei_ont_wan_ip_changed_total",
+        "Times the WAN IP differed from the one seen on the previous scrape with an IP",
+        &["target"]
     )
     .expect("metric registration failed");
 
     // Client Metrics
-    pub static ref LAN_CLIENTS: Gauge = register_gauge!(
+    pub static ref LAN_CLIENTS: GaugeVec = register_gauge_vec!(
         "huawei_ont_lan_clients",
-        "Number of connected LAN clients"
+        "Number of connected LAN clients",
+        &["target"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref CLIENT_LEASE_REMAINING: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new("huawei_ont_client_lease_seconds_remaining", "Remaining DHCP lease of each LAN client in seconds"),
+        &["target", "mac"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref CLIENT_RSSI: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new("huawei_ont_client_rssi_dbm", "Signal strength of each wireless client in dBm"),
+        &["target", "mac", "ssid"]
     )
     .expect("metric registration failed");
 
-    pub static ref WIFI_CLIENTS: Gauge = register_gauge!(
+    pub static ref WIFI_SSID_ENABLED: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new("huawei_ont_wifi_ssid_enabled", "Whether the SSID is enabled (1=on, 0=off)"),
+        &["target", "ssid", "band"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref WIFI_SSID_INFO: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new("huawei_ont_wifi_ssid_info", "SSID configuration (always 1)"),
+        &["target", "ssid", "security", "band"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref WIFI_CLIENTS: GaugeVec = register_gauge_vec!(
         "huawei_ont_wifi_clients",
-        "Number of connected WiFi clients"
+        "Number of connected WiFi clients",
+        &["target"]
     )
     .expect("metric registration failed");
 
-    pub static ref TOTAL_CLIENTS: Gauge = register_gauge!(
+    pub static ref TOTAL_CLIENTS: GaugeVec = register_gauge_vec!(
         "huawei_ont_total_clients",
-        "Total number of connected clients"
+        "Total number of connected clients",
+        &["target"]
     )
     .expect("metric registration failed");
 
     // Scrape Metrics
-    pub static ref SCRAPE_DURATION: Histogram = register_histogram!(
+    pub static ref ACTIVE_URL: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new("huawei_ont_active_url", "ONT URL used for the last successful scrape (1=active)"),
+        &["target", "url"]
+    )
+    .expect("metric registration failed");
+    pub static ref PAGE_AVAILABLE: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new("huawei_ont_page_available", "Whether a candidate page path served the expected content (1=yes, 0=no)"),
+        &["target", "page"]
+    )
+    .expect("metric registration failed");
+    pub static ref SECTION_PARSE_SUCCESS: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new("huawei_ont_section_parse_success", "Whether an optional section was fetched and parsed in the last scrape (1=yes, 0=no)"),
+        &["target", "section"]
+    )
+    .expect("metric registration failed");
+    pub static ref LAN_CLIENTS_PARSED: IntGaugeVec = register_int_gauge_vec!(
+        "huawei_ont_lan_clients_parsed",
+        "Client entries parsed from the LAN page in the last scrape",
+        &["target"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref LAN_CLIENTS_DECLARED: IntGaugeVec = register_int_gauge_vec!(
+        "huawei_ont_lan_clients_declared",
+        "Client count the LAN page declares itself (when reported), differs from parsed on truncation",
+        &["target"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref ENTITIES_PARSED: IntGaugeVec = register_int_gauge_vec!(
+        "huawei_ont_entities_parsed",
+        "Entries parsed in the last scrape by kind (wan_connections, wifi_ssids, optical_modules)",
+        &["target", "kind"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref SCRAPE_SECTIONS_PARSED: IntGaugeVec = register_int_gauge_vec!(
+        "huawei_ont_scrape_sections_parsed",
+        "Number of enabled optional sections (device, wan, lan, wlan) parsed in the last scrape",
+        &["target"]
+    )
+    .expect("metric registration failed");
+    pub static ref SECTION_LAST_SUCCESS: GaugeVec = register_gauge_vec!(
+        "huawei_ont_section_last_success_timestamp_seconds",
+        "Unix time each section (optical, device, wan, ...) was last fetched and parsed",
+        &["target", "section"]
+    )
+    .expect("metric registration failed");
+    pub static ref SCRAPE_DURATION: HistogramVec = register_histogram_vec!(
         "huawei_ont_scrape_duration_seconds",
         "Duration of ONT scrape in seconds",
+        &["target"],
         vec![0.01, 0.05, 0.1, 0.5, 1.0, 2.0, 5.0, 10.0]
     )
     .expect("metric registration failed");
-    pub static ref SCRAPE_ERRORS: Counter = register_counter!(
+    pub static ref SCRAPE_ERRORS: CounterVec = register_counter_vec!(
         "huawei_ont_scrape_errors_total",
-        "Total number of scrape errors"
+        "Total number of scrape errors by reason (network, http, parse, auth)",
+        &["target", "reason"]
     )
     .expect("metric registration failed");
-    pub static ref SCRAPES_TOTAL: Counter = register_counter!(
+    pub static ref SCRAPE_MISSED_TICKS: IntCounterVec = register_int_counter_vec!(
+        "huawei_ont_scrape_missed_ticks_total",
+        "Scrape intervals skipped because the previous scrape overran",
+        &["target"]
+    )
+    .expect("metric registration failed");
+    pub static ref SCRAPE_IN_PROGRESS: IntGaugeVec = register_int_gauge_vec!(
+        "huawei_ont_scrape_in_progress",
+        "Number of scrapes currently running (normally 0 or 1)",
+        &["target"]
+    )
+    .expect("metric registration failed");
+    pub static ref SCRAPES_TOTAL: CounterVec = register_counter_vec!(
         "huawei_ont_scrapes_total",
-        "Total number of scrapes attempted"
+        "Total number of scrapes attempted",
+        &["target"]
+    )
+    .expect("metric registration failed");
+
+    // Outcome of the last /probe, set before it renders so a failed probe still reports
+    pub static ref PROBE_SUCCESS: IntGaugeVec = register_int_gauge_vec!(
+        "huawei_ont_probe_success",
+        "Whether the last /probe scrape succeeded (1=success, 0=failure)",
+        &["target"]
+    )
+    .expect("metric registration failed");
+    pub static ref PROBE_DURATION: GaugeVec = register_gauge_vec!(
+        "huawei_ont_probe_duration_seconds",
+        "Duration of the last /probe scrape in seconds",
+        &["target"]
+    )
+    .expect("metric registration failed");
+    pub static ref PROBE_ERROR_INFO: IntGaugeVec = register_int_gauge_vec!(
+        "huawei_ont_probe_error_info",
+        "Reason the last /probe scrape failed (network, http, parse, auth), absent after a success",
+        &["target", "reason"]
+    )
+    .expect("metric registration failed");
+
+    // Login Metrics
+    pub static ref LOGIN_ATTEMPTS: CounterVec = register_counter_vec!(
+        "huawei_ont_login_attempts_total",
+        "Total number of login attempts",
+        &["target"]
+    )
+    .expect("metric registration failed");
+    pub static ref LOGIN_TOKEN_LENGTH: IntGaugeVec = register_int_gauge_vec!(
+        "huawei_ont_login_token_length",
+        "Length of the last GetRandCount response, trimmed (0 or a page-sized value means no usable token)",
+        &["target"]
+    )
+    .expect("metric registration failed");
+    pub static ref LOGIN_FAILURES: CounterVec = register_counter_vec!(
+        Opts::new("huawei_ont_login_failures_total", "Total number of failed logins by stage"),
+        &["target", "stage"]
+    )
+    .expect("metric registration failed");
+
+    // ONT Client Metrics (traffic generated by the exporter)
+    pub static ref CLIENT_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        Opts::new("huawei_ont_client_requests_total", "Total number of requests sent to the ONT by page"),
+        &["target", "page"]
+    )
+    .expect("metric registration failed");
+    pub static ref CLIENT_RESPONSE_BYTES: IntCounterVec = register_int_counter_vec!(
+        Opts::new("huawei_ont_client_response_bytes_total", "Total response body bytes read from the ONT by page"),
+        &["target", "page"]
+    )
+    .expect("metric registration failed");
+
+    pub static ref CLIENT_PROTOCOL_INFO: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "huawei_ont_client_protocol_info",
+            "HTTP version and whether TLS was used on the last optical page response (always 1)"
+        ),
+        &["target", "http_version", "tls"]
+    )
+    .expect("metric registration failed");
+
+    // Always 1 while the exporter is serving, independent of scrape success
+    pub static ref EXPORTER_UP: IntGauge = register_int_gauge!(
+        "huawei_ont_exporter_up",
+        "Always 1 while the exporter is running"
     )
     .expect("metric registration failed");
 
@@ -111,61 +426,743 @@ lazy_static! {
     .expect("metric registration failed");
 }
 
-pub fn update_metrics(data: &OntMetrics) {
-    // Optical metrics (always present)
-    TX_POWER.set(data.tx_power);
-    RX_POWER.set(data.rx_power);
-    VOLTAGE.set(data.voltage);
-    BIAS_CURRENT.set(data.bias_current);
-    TEMPERATURE.set(data.temperature);
+/// Every label huawei_ont_device_info can carry, in exposition order
+pub const DEVICE_INFO_LABELS: &[&str] = &[
+    "model",
+    "serial",
+    "hardware_version",
+    "software_version",
+    "mac_address",
+    "customization",
+];
 
-    // Device info metrics with labels
-    let model = data.device_model.as_deref().unwrap_or("unknown");
-    let serial = data.serial_number.as_deref().unwrap_or("unknown");
-    let hw_version = data.hardware_version.as_deref().unwrap_or("unknown");
-    let sw_version = data.software_version.as_deref().unwrap_or("unknown");
-    let mac = data.mac_address.as_deref().unwrap_or("unknown");
-    DEVICE_INFO
-        .with_label_values(&[model, serial, hw_version, sw_version, mac])
-        .set(1);
+// The label set is configurable, so this one is registered at startup rather than lazily
+static DEVICE_INFO: OnceLock<IntGaugeVec> = OnceLock::new();
+
+pub fn register_device_info(labels: &[&str]) {
+    let labels: Vec<&str> = std::iter::once("target").chain(labels.iter().copied()).collect();
+    let gauge = register_int_gauge_vec!(
+        Opts::new("huawei_ont_device_info", "Device information (always 1)"),
+        &labels
+    )
+    .expect("metric registration failed");
+    let _ = DEVICE_INFO.set(gauge);
+}
+
+// update_metrics holds this for writing and gather() for reading, so an exposition
+// never mixes values from two different scrapes
+static SNAPSHOT_LOCK: RwLock<()> = RwLock::new(());
+
+// When update_metrics last applied a successful scrape, per target
+static LAST_SUCCESS: Mutex<BTreeMap<String, SystemTime>> = Mutex::new(BTreeMap::new());
+
+// The scrape update_metrics last applied per target, for the non-Prometheus outputs
+static LATEST: Mutex<BTreeMap<String, OntMetrics>> = Mutex::new(BTreeMap::new());
+
+pub fn latest_metrics(target: &str) -> Option<OntMetrics> {
+    LATEST.lock().unwrap().get(target).cloned()
+}
+
+/// Removes every series of one target from a labeled metric, leaving other targets alone
+pub fn reset_target<T: MetricVecBuilder>(vec: &MetricVec<T>, target: &str) {
+    let families = vec.collect();
+    for metric in families.iter().flat_map(|family| family.get_metric()) {
+        let labels: HashMap<&str, &str> = metric.get_label().iter().map(|l| (l.name(), l.value())).collect();
+        if labels.get("target") == Some(&target) {
+            let _ = vec.remove(&labels);
+        }
+    }
+}
+
+/// Records the outcome of a /probe scrape; `error` is its error_reason on failure
+pub fn record_probe(target: &str, duration: Duration, error: Option<&str>) {
+    let _snapshot = SNAPSHOT_LOCK.write().unwrap();
+    PROBE_SUCCESS.with_label_values(&[target]).set(error.is_none() as i64);
+    PROBE_DURATION.with_label_values(&[target]).set(duration.as_secs_f64());
+    reset_target(&PROBE_ERROR_INFO, target);
+    if let Some(reason) = error {
+        PROBE_ERROR_INFO.with_label_values(&[target, reason]).set(1);
+    }
+}
+
+/// Whether any scrape has succeeded since startup, for /ready
+pub fn has_scraped() -> bool {
+    !LAST_SUCCESS.lock().unwrap().is_empty()
+}
+
+/// Gathers the default registry as one coherent snapshot of the last scrape
+pub fn gather() -> Vec<prometheus::proto::MetricFamily> {
+    let _snapshot = SNAPSHOT_LOCK.read().unwrap();
+    prometheus::gather()
+}
+
+pub fn update_metrics(target: &str, data: &OntMetrics, config: &Config) {
+    let _snapshot = SNAPSHOT_LOCK.write().unwrap();
+    let max = config.max_label_len;
+
+    // Optical metrics, left at their last values while the link is down
+    OPTICAL_PRESENT.with_label_values(&[target]).set(data.optical_present as i64);
+    if let Some(count) = data.optical_field_count {
+        OPTICAL_FIELD_COUNT.with_label_values(&[target]).set(count as i64);
+    }
+    if data.optical_present {
+        // Reset so a module that lost its link on a multi-PON unit doesn't linger
+        for gauge in [
+            &*TX_POWER,
+            &*RX_POWER,
+            &*TX_POWER_NOMINAL,
+            &*RX_POWER_NOMINAL,
+            &*VOLTAGE,
+            &*BIAS_CURRENT,
+            &*TEMPERATURE,
+        ] {
+            reset_target(gauge, target);
+        }
+        for module in &data.optical_modules {
+            let port = module.port.as_str();
+            TX_POWER.with_label_values(&[target, port]).set(module.tx_power);
+            RX_POWER.with_label_values(&[target, port]).set(module.rx_power);
+            VOLTAGE.with_label_values(&[target, port]).set(module.voltage);
+            BIAS_CURRENT.with_label_values(&[target, port]).set(module.bias_current);
+            TEMPERATURE.with_label_values(&[target, port]).set(module.temperature);
+            if let Some(tx_nominal) = module.tx_power_nominal {
+                TX_POWER_NOMINAL.with_label_values(&[target, port]).set(tx_nominal);
+            }
+            if let Some(rx_nominal) = module.rx_power_nominal {
+                RX_POWER_NOMINAL.with_label_values(&[target, port]).set(rx_nominal);
+            }
+        }
+
+        // Prefer the device-reported sensitivity, fall back to the configured one
+        if let Some(floor) = data.rx_sensitivity.or(config.rx_sensitivity_dbm) {
+            RX_HEADROOM.with_label_values(&[target]).set(data.rx_power - floor);
+        }
+        if let Some(baseline) = config.rx_power_baseline_dbm {
+            RX_POWER_DELTA.with_label_values(&[target]).set(data.rx_power - baseline);
+        }
+        if config.rx_power_histogram && !data.rx_power.is_nan() {
+            RX_POWER_HISTOGRAM.with_label_values(&[target]).observe(data.rx_power);
+        }
+    }
+
+    if let Some(alarm) = data.laser_alarm {
+        LASER_ALARM.with_label_values(&[target]).set(if alarm { 1.0 } else { 0.0 });
+    }
+    if let Some(alarm) = data.los_alarm {
+        OPTICAL_LOS.with_label_values(&[target]).set(if alarm { 1.0 } else { 0.0 });
+    }
+    if let Some(alarm) = data.lof_alarm {
+        OPTICAL_LOF.with_label_values(&[target]).set(if alarm { 1.0 } else { 0.0 });
+    }
+
+    // Reset so a new ONU ID or LOID replaces the old series
+    if data.onu_id.is_some() || data.loid.is_some() {
+        reset_target(&GPON_REGISTRATION_INFO, target);
+        GPON_REGISTRATION_INFO
+            .with_label_values(&[
+                target,
+                bound_label(data.onu_id.as_deref().unwrap_or(""), max),
+                bound_label(data.loid.as_deref().unwrap_or(""), max),
+            ])
+            .set(data.onu_id.is_some() as i64);
+    }
+
+    // GPON error counters (optional)
+    if let Some(fec) = data.fec_corrected {
+        advance_counter(&GPON_FEC_CORRECTED, target, "fec_corrected", fec);
+    }
+    if let Some(bip) = data.bip_errors {
+        advance_counter(&GPON_BIP_ERRORS, target, "bip_errors", bip);
+    }
+
+    // Sections fail independently, so each gets its own freshness timestamp
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+    if data.optical_present {
+        SECTION_LAST_SUCCESS.with_label_values(&[target, "optical"]).set(now);
+    }
+    for (section, ok) in &data.sections {
+        SECTION_PARSE_SUCCESS.with_label_values(&[target, *section]).set(*ok as i64);
+        if *ok {
+            SECTION_LAST_SUCCESS.with_label_values(&[target, *section]).set(now);
+        }
+    }
+    // So a list cut short by a truncated response shows up as a drop here
+    for (kind, parsed, count) in [
+        ("wan_connections", data.sections.get("wan") == Some(&true), data.wan_connections.len()),
+        ("wifi_ssids", data.sections.get("wlan") == Some(&true), data.wifi_ssids.len()),
+        ("optical_modules", data.optical_present, data.optical_modules.len()),
+    ] {
+        if parsed {
+            ENTITIES_PARSED.with_label_values(&[target, kind]).set(count as i64);
+        }
+    }
+
+    // Compare with count(huawei_ont_section_parse_success) for "n of m sections"
+    SCRAPE_SECTIONS_PARSED.with_label_values(&[target]).set(data.sections.values().filter(|ok| **ok).count() as i64);
+
+    // Device info metrics with the configured subset of labels
+    if let Some(device_info) = DEVICE_INFO.get() {
+        let values = [
+            &data.device_model,
+            &data.serial_number,
+            &data.hardware_version,
+            &data.software_version,
+            &data.mac_address,
+            &data.customization,
+        ];
+        let selected = DEVICE_INFO_LABELS
+            .iter()
+            .zip(values)
+            .filter(|(label, _)| config.device_info_labels.contains(label))
+            .map(|(_, value)| bound_label(value.as_deref().unwrap_or("unknown"), max));
+        let selected: Vec<&str> = std::iter::once(target).chain(selected).collect();
+        device_info.with_label_values(&selected).set(1);
+    }
 
     // Uptime metric
     if let Some(uptime) = data.uptime_seconds {
-        UPTIME.set(uptime as f64);
-    }
-
-    // WAN metrics (optional)
-    if let Some(status) = &data.wan_status {
-        let status_value = if status.eq_ignore_ascii_case("connected")
-            || status.eq_ignore_ascii_case("up")
-            || status.eq_ignore_ascii_case("online")
-        {
-            1.0
-        } else {
-            0.0
-        };
-        let ip = data.wan_ip.as_deref().unwrap_or("unknown");
-        WAN_STATUS.with_label_values(&[ip]).set(status_value as i64);
+        UPTIME.with_label_values(&[target]).set(uptime as f64);
+    }
+
+    // Device clock (only reported by some models)
+    if let Some(synced) = data.time_synced {
+        TIME_SYNCED.with_label_values(&[target]).set(synced as i64);
+    }
+    if let Some(time) = data.device_time_seconds {
+        DEVICE_TIME.with_label_values(&[target]).set(time as f64);
     }
 
-    if let Some(rx_bytes) = data.wan_rx_bytes {
-        WAN_RX_BYTES.set(rx_bytes as f64);
+    // CPU and memory load (only reported by some models)
+    if let Some(cpu) = data.cpu_usage_percent {
+        CPU_USAGE.with_label_values(&[target]).set(cpu);
+    }
+    if let Some(memory) = data.memory_usage_percent {
+        MEMORY_USAGE.with_label_values(&[target]).set(memory);
     }
 
-    if let Some(tx_bytes) = data.wan_tx_bytes {
-        WAN_TX_BYTES.set(tx_bytes as f64);
+    // WAN metrics (optional), one series per connection
+    let single_wan;
+    let connections = if !data.wan_connections.is_empty() {
+        data.wan_connections.as_slice()
+    } else if data.wan_status.is_some() {
+        // Only the aggregate view was available (e.g. from the optical page)
+        single_wan = [WanConnection {
+            name: "wan".to_string(),
+            service: "unknown".to_string(),
+            status: data.wan_status.clone(),
+            ip: data.wan_ip.clone(),
+            rx_bytes: data.wan_rx_bytes,
+            tx_bytes: data.wan_tx_bytes,
+            last_error: None,
+        }];
+        &single_wan[..]
+    } else {
+        &[]
+    };
+    if !connections.is_empty() {
+        // Reset so a changed IP or a removed connection doesn't linger
+        reset_target(&WAN_STATUS, target);
+        reset_target(&WAN_RX_BYTES, target);
+        reset_target(&WAN_TX_BYTES, target);
+        reset_target(&WAN_LAST_ERROR_INFO, target);
+    }
+    for wan in connections {
+        let up = wan.status.as_deref().is_some_and(|status| {
+            status.eq_ignore_ascii_case("connected")
+                || status.eq_ignore_ascii_case("up")
+                || status.eq_ignore_ascii_case("online")
+        });
+        let ip = wan.ip.as_deref().unwrap_or("unknown");
+        let name = bound_label(&wan.name, max);
+        WAN_STATUS
+            .with_label_values(&[target, name, bound_label(&wan.service, max), bound_label(ip, max)])
+            .set(up as i64);
+        if let Some(rx_bytes) = wan.rx_bytes {
+            WAN_RX_BYTES.with_label_values(&[target, name]).set(rx_bytes as f64);
+        }
+        if let Some(tx_bytes) = wan.tx_bytes {
+            WAN_TX_BYTES.with_label_values(&[target, name]).set(tx_bytes as f64);
+        }
+        if let Some(error) = &wan.last_error {
+            WAN_LAST_ERROR_INFO.with_label_values(&[target, name, bound_label(error, max)]).set(1);
+        }
     }
 
     // Client metrics (optional)
     if let Some(lan_count) = data.lan_clients_count {
-        LAN_CLIENTS.set(lan_count as f64);
+        LAN_CLIENTS.with_label_values(&[target]).set(lan_count as f64);
     }
 
     if let Some(wifi_count) = data.wifi_clients_count {
-        WIFI_CLIENTS.set(wifi_count as f64);
+        WIFI_CLIENTS.with_label_values(&[target]).set(wifi_count as f64);
     }
 
     if let Some(total_count) = data.total_clients_count {
-        TOTAL_CLIENTS.set(total_count as f64);
+        TOTAL_CLIENTS.with_label_values(&[target]).set(total_count as f64);
+    }
+
+    // Reset so clients that left (or switched to a static IP) drop out
+    if data.sections.get("lan") == Some(&true) {
+        LAN_CLIENTS_PARSED.with_label_values(&[target]).set(data.total_clients_count.unwrap_or(0) as i64);
+        if let Some(declared) = data.lan_clients_declared {
+            LAN_CLIENTS_DECLARED.with_label_values(&[target]).set(declared as i64);
+        }
+        reset_target(&CLIENT_LEASE_REMAINING, target);
+        for (mac, remaining) in &data.client_leases {
+            CLIENT_LEASE_REMAINING.with_label_values(&[target, bound_label(mac, max)]).set(*remaining as i64);
+        }
+        reset_target(&CLIENT_RSSI, target);
+        for client in &data.client_rssi {
+            CLIENT_RSSI
+                .with_label_values(&[target, bound_label(&client.mac, max), bound_label(&client.ssid, max)])
+                .set(client.rssi_dbm);
+        }
+    }
+
+    // Reset so renamed SSIDs or changed security modes don't leave stale series
+    if data.sections.get("wlan") == Some(&true) {
+        reset_target(&WIFI_SSID_ENABLED, target);
+        reset_target(&WIFI_SSID_INFO, target);
+        for ssid in &data.wifi_ssids {
+            let name = bound_label(&ssid.ssid, max);
+            WIFI_SSID_ENABLED.with_label_values(&[target, name, ssid.band]).set(ssid.enabled as i64);
+            WIFI_SSID_INFO
+                .with_label_values(&[target, name, bound_label(&ssid.security, max), ssid.band])
+                .set(1);
+        }
+    }
+
+    LATEST.lock().unwrap().insert(target.to_string(), data.clone());
+    LAST_SUCCESS.lock().unwrap().insert(target.to_string(), SystemTime::now());
+}
+
+// Label values come from the device's HTML, so a malformed device name or a hostile
+// client hostname could otherwise add arbitrarily large series
+fn bound_label(value: &str, max: usize) -> &str {
+    if value.len() <= max {
+        return value;
+    }
+    let end = (0..=max).rev().find(|&i| value.is_char_boundary(i)).unwrap_or(0);
+    if first_truncation(&value[..end]) {
+        warn!("Truncated a {}-byte label value to {} bytes: {:?}", value.len(), end, &value[..end]);
+    } else {
+        debug!("Truncated a {}-byte label value to {} bytes: {:?}", value.len(), end, &value[..end]);
+    }
+    &value[..end]
+}
+
+// Truncated label values already warned about, so the same oversized value doesn't warn
+// on every scrape. Capped so a stream of distinct hostile values can't grow it forever.
+static TRUNCATED_LABELS: Mutex<BTreeSet<String>> = Mutex::new(BTreeSet::new());
+const MAX_TRUNCATED_LABELS: usize = 1000;
+
+fn first_truncation(truncated: &str) -> bool {
+    let mut seen = TRUNCATED_LABELS.lock().unwrap();
+    seen.len() < MAX_TRUNCATED_LABELS && seen.insert(truncated.to_string())
+}
+
+/// Apply MASK_WAN_IP to every WAN address of a scrape, before it is exported, logged
+/// or returned by /scrape
+pub fn mask_wan_ips(data: &mut OntMetrics, mask: WanIpMask) {
+    let ips = std::iter::once(&mut data.wan_ip).chain(data.wan_connections.iter_mut().map(|wan| &mut wan.ip));
+    for ip in ips.flatten() {
+        *ip = mask_wan_ip(ip, mask);
+    }
+}
+
+// SipHash rather than DefaultHasher, whose output may change between Rust releases
+// and would then break dashboards keyed on the hash
+fn mask_wan_ip(ip: &str, mask: WanIpMask) -> String {
+    match mask {
+        WanIpMask::Off => ip.to_string(),
+        WanIpMask::Redact => "redacted".to_string(),
+        WanIpMask::Hash { key: (k0, k1) } => {
+            let mut hasher = SipHasher24::new_with_keys(k0, k1);
+            hasher.write(ip.as_bytes());
+            format!("{:012x}", hasher.finish() >> 16)
+        }
+    }
+}
+
+/// Flat `name value` lines of the numeric fields of a scrape, for pipelines such as
+/// collectd that don't speak the Prometheus format. Nested fields are joined with `_`
+/// (e.g. `huawei_ont_sections_wan 1`, `huawei_ont_wan_connections_0_rx_bytes 123`),
+/// booleans are 1/0 and string fields are left out.
+pub fn encode_plain(data: &OntMetrics) -> String {
+    const OPTICAL_FIELDS: &[&str] = &["tx_power", "rx_power", "voltage", "bias_current", "temperature"];
+
+    let mut value = serde_json::to_value(data).unwrap_or_default();
+    // Link down: the zeroed optical fields would read as real values
+    if !data.optical_present
+        && let Some(fields) = value.as_object_mut()
+    {
+        fields.retain(|name, _| !OPTICAL_FIELDS.contains(&name.as_str()));
+    }
+
+    let mut out = String::new();
+    flatten_plain("huawei_ont", &value, &mut out);
+    out
+}
+
+fn flatten_plain(name: &str, value: &serde_json::Value, out: &mut String) {
+    use serde_json::Value;
+    match value {
+        Value::Number(n) => out.push_str(&format!("{} {}\n", name, n)),
+        Value::Bool(b) => out.push_str(&format!("{} {}\n", name, *b as u8)),
+        Value::Object(fields) => {
+            for (key, field) in fields {
+                flatten_plain(&format!("{}_{}", name, key), field, out);
+            }
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter().enumerate() {
+                flatten_plain(&format!("{}_{}", name, index), item, out);
+            }
+        }
+        Value::String(_) | Value::Null => {}
+    }
+}
+
+/// Reports how old the exposed ONT data is, computed at collection time so it keeps
+/// growing while scrapes fail instead of freezing with the other gauges
+pub struct FreshnessCollector {
+    descs: Vec<Desc>,
+    last_success: GaugeVec,
+    age: GaugeVec,
+}
+
+impl FreshnessCollector {
+    pub fn new() -> Self {
+        let last_success = GaugeVec::new(
+            Opts::new(
+                "huawei_ont_last_success_timestamp_seconds",
+                "Unix time of the last scrape whose data is currently exposed",
+            ),
+            &["target"],
+        )
+        .unwrap();
+        let age = GaugeVec::new(
+            Opts::new(
+                "huawei_ont_metrics_age_seconds",
+                "Seconds since the last successful scrape, computed when /metrics is read",
+            ),
+            &["target"],
+        )
+        .unwrap();
+
+        let mut descs = Vec::new();
+        descs.extend(last_success.desc().into_iter().cloned());
+        descs.extend(age.desc().into_iter().cloned());
+
+        Self {
+            descs,
+            last_success,
+            age,
+        }
+    }
+}
+
+impl Collector for FreshnessCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<prometheus::proto::MetricFamily> {
+        // Nothing to report until the first successful scrape
+        let last_success = LAST_SUCCESS.lock().unwrap();
+        if last_success.is_empty() {
+            return Vec::new();
+        }
+        for (target, at) in last_success.iter() {
+            let since_epoch = at.duration_since(UNIX_EPOCH).unwrap_or_default();
+            self.last_success.with_label_values(&[target]).set(since_epoch.as_secs_f64());
+            self.age.with_label_values(&[target]).set(at.elapsed().unwrap_or_default().as_secs_f64());
+        }
+
+        let mut mfs = self.last_success.collect();
+        mfs.extend(self.age.collect());
+        mfs
+    }
+}
+
+pub fn register_freshness_collector() {
+    if let Err(e) = prometheus::register(Box::new(FreshnessCollector::new())) {
+        tracing::error!("Failed to register freshness collector: {}", e);
+    }
+}
+
+// Turn a device-side cumulative value into counter increments. A value lower than
+// the previous one means the device counter was reset (e.g. reboot).
+fn advance_counter(counter: &IntCounterVec, target: &str, key: &'static str, raw: u64) {
+    let mut last = LAST_COUNTER_VALUES.lock().unwrap();
+    let delta = match last.insert((target.to_string(), key), raw) {
+        Some(prev) if raw >= prev => raw - prev,
+        _ => raw,
+    };
+    counter.with_label_values(&[target]).inc_by(delta);
+}
+
+/// Rolling window of rx power samples for huawei_ont_optical_rx_power_slope_db_per_hour.
+/// A steady decline shows a degrading fiber or connector before the link drops.
+pub struct RxPowerTrend {
+    window: usize,
+    samples: VecDeque<(Instant, f64)>,
+}
+
+impl RxPowerTrend {
+    pub fn new(window: usize) -> Self {
+        Self {
+            window,
+            samples: VecDeque::with_capacity(window),
+        }
+    }
+
+    /// Add a sample, dropping the oldest once the window is full
+    pub fn record(&mut self, at: Instant, rx_power: f64) {
+        if self.window == 0 {
+            return;
+        }
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((at, rx_power));
+    }
+
+    /// Least-squares slope in dB per hour, None until two samples apart in time exist
+    pub fn slope_db_per_hour(&self) -> Option<f64> {
+        let (first, _) = *self.samples.front()?;
+        let n = self.samples.len() as f64;
+        let points = || self.samples.iter().map(|(at, rx)| (at.duration_since(first).as_secs_f64() / 3600.0, *rx));
+
+        let mean_x = points().map(|(x, _)| x).sum::<f64>() / n;
+        let mean_y = points().map(|(_, y)| y).sum::<f64>() / n;
+        let (cov, var) = points().fold((0.0, 0.0), |(cov, var), (x, y)| {
+            (cov + (x - mean_x) * (y - mean_y), var + (x - mean_x).powi(2))
+        });
+
+        (var > 0.0).then(|| cov / var)
+    }
+}
+
+/// Last WAN IP seen by the scrape loop, for huawei_ont_wan_ip_changed_total. Scrapes
+/// without an address (failures, a down WAN showing 0.0.0.0) are skipped rather than
+/// counted, so only a move from one real address to another is a change.
+#[derive(Default)]
+pub struct WanIpTracker {
+    last: Option<String>,
+}
+
+impl WanIpTracker {
+    /// Whether `ip` differs from the last address seen, None when there is no address
+    pub fn observe(&mut self, ip: Option<&str>) -> Option<bool> {
+        let ip = ip.map(str::trim).filter(|ip| !ip.is_empty() && *ip != "0.0.0.0")?;
+        let changed = self.last.as_deref().is_some_and(|last| last != ip);
+        self.last = Some(ip.to_string());
+        Some(changed)
+    }
+}
+
+pub const OPENMETRICS_FORMAT: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Encode in the OpenMetrics text format. The prometheus crate only ships the classic
+/// text encoder, whose output differs from OpenMetrics in just two ways for the
+/// metric types we use: counter families are named without the `_total` suffix
+/// (samples keep it), and the exposition ends with `# EOF`.
+pub fn encode_openmetrics(families: &[prometheus::proto::MetricFamily]) -> prometheus::Result<String> {
+    let text = prometheus::TextEncoder::new().encode_to_string(families)?;
+
+    let counters: Vec<&str> = families
+        .iter()
+        .filter(|mf| mf.get_field_type() == prometheus::proto::MetricType::COUNTER)
+        .map(|mf| mf.name())
+        .collect();
+
+    let mut out = String::with_capacity(text.len() + 6);
+    for line in text.lines() {
+        let metadata = line
+            .strip_prefix("# HELP ")
+            .map(|rest| ("# HELP ", rest))
+            .or_else(|| line.strip_prefix("# TYPE ").map(|rest| ("# TYPE ", rest)));
+
+        match metadata {
+            Some((prefix, rest)) => {
+                let (name, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+                let name = if counters.contains(&name) {
+                    name.strip_suffix("_total").unwrap_or(name)
+                } else {
+                    name
+                };
+                out.push_str(prefix);
+                out.push_str(name);
+                out.push(' ');
+                out.push_str(tail);
+            }
+            None => out.push_str(line),
+        }
+        out.push('\n');
+    }
+    out.push_str("# EOF\n");
+
+    Ok(out)
+}
+
+/// Register the prometheus crate's `process_*` metrics for the exporter itself.
+#[cfg(target_os = "linux")]
+pub fn register_process_collector() {
+    let collector = prometheus::process_collector::ProcessCollector::for_self();
+    if let Err(e) = prometheus::register(Box::new(collector)) {
+        tracing::error!("Failed to register process collector: {}", e);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn register_process_collector() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Gauge, Registry};
+
+    #[test]
+    fn test_reset_target() {
+        let wan = GaugeVec::new(Opts::new("test_wan_rx_bytes", "WAN rx"), &["target", "name"]).unwrap();
+        wan.with_label_values(&["http://a", "wan1"]).set(1.0);
+        wan.with_label_values(&["http://a", "wan2"]).set(2.0);
+        wan.with_label_values(&["http://b", "wan1"]).set(3.0);
+
+        reset_target(&wan, "http://a");
+
+        let families = wan.collect();
+        let metrics = families[0].get_metric();
+        assert_eq!(metrics.len(), 1);
+        assert_eq!(wan.with_label_values(&["http://b", "wan1"]).get(), 3.0);
+    }
+
+    #[test]
+    fn test_bound_label() {
+        assert_eq!(bound_label("HG8145V5", 128), "HG8145V5");
+        assert_eq!(bound_label("abcdef", 4), "abcd");
+        // Never splits a multi-byte character
+        assert_eq!(bound_label("caf\u{e9}", 4), "caf");
+
+        // Warned about once per value, not on every scrape
+        assert!(first_truncation("first-truncation-test"));
+        assert!(!first_truncation("first-truncation-test"));
+    }
+
+    #[test]
+    fn test_mask_wan_ip() {
+        assert_eq!(mask_wan_ip("203.0.113.7", WanIpMask::Off), "203.0.113.7");
+        assert_eq!(mask_wan_ip("203.0.113.7", WanIpMask::Redact), "redacted");
+
+        let hash = WanIpMask::Hash { key: (1, 2) };
+        let hashed = mask_wan_ip("203.0.113.7", hash);
+        assert_eq!(hashed.len(), 12);
+        assert_eq!(hashed, mask_wan_ip("203.0.113.7", hash));
+        assert_ne!(hashed, mask_wan_ip("203.0.113.8", hash));
+        // Another salt gives unrelated hashes
+        assert_ne!(hashed, mask_wan_ip("203.0.113.7", WanIpMask::Hash { key: (3, 4) }));
+
+        let mut data = OntMetrics {
+            wan_ip: Some("203.0.113.7".to_string()),
+            wan_connections: vec![WanConnection {
+                name: "wan".to_string(),
+                service: "INTERNET".to_string(),
+                status: None,
+                ip: Some("203.0.113.7".to_string()),
+                rx_bytes: None,
+                tx_bytes: None,
+                last_error: None,
+            }],
+            ..Default::default()
+        };
+        mask_wan_ips(&mut data, WanIpMask::Redact);
+        assert_eq!(data.wan_ip.as_deref(), Some("redacted"));
+        assert_eq!(data.wan_connections[0].ip.as_deref(), Some("redacted"));
+    }
+
+    #[test]
+    fn test_encode_openmetrics() {
+        let registry = Registry::new();
+        let counter = Counter::new("test_requests_total", "Requests").unwrap();
+        let gauge = Gauge::new("test_temperature_celsius", "Temperature").unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        registry.register(Box::new(gauge.clone())).unwrap();
+        counter.inc();
+        gauge.set(47.0);
+
+        let out = encode_openmetrics(&registry.gather()).unwrap();
+        assert!(out.contains("# TYPE test_requests counter\n"));
+        assert!(out.contains("# HELP test_requests Requests\n"));
+        assert!(out.contains("test_requests_total 1\n"));
+        assert!(out.contains("# TYPE test_temperature_celsius gauge\n"));
+        assert!(out.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn test_rx_power_trend() {
+        let start = Instant::now();
+        let at = |mins: u64| start + std::time::Duration::from_secs(mins * 60);
+        let mut trend = RxPowerTrend::new(3);
+
+        trend.record(at(0), -20.0);
+        assert_eq!(trend.slope_db_per_hour(), None);
+
+        // -0.5 dB every 30 minutes, the -10 dBm outlier falls out of the window
+        trend.record(at(30), -10.0);
+        trend.record(at(60), -21.0);
+        trend.record(at(90), -21.5);
+        trend.record(at(120), -22.0);
+        let slope = trend.slope_db_per_hour().unwrap();
+        assert!((slope - -1.0).abs() < 1e-9, "slope {}", slope);
+    }
+
+    #[test]
+    fn test_wan_ip_tracker() {
+        let mut tracker = WanIpTracker::default();
+        assert_eq!(tracker.observe(Some("203.0.113.7")), Some(false));
+        assert_eq!(tracker.observe(None), None);
+        assert_eq!(tracker.observe(Some("0.0.0.0")), None);
+        assert_eq!(tracker.observe(Some("203.0.113.7")), Some(false));
+        assert_eq!(tracker.observe(Some("198.51.100.4")), Some(true));
+    }
+
+    #[test]
+    fn test_encode_plain() {
+        let mut data = OntMetrics {
+            optical_present: true,
+            rx_power: -24.09,
+            serial_number: Some("4857544345AABBCC".to_string()),
+            uptime_seconds: Some(86400),
+            ..Default::default()
+        };
+        data.sections.insert("wan", false);
+
+        let out = encode_plain(&data);
+        assert!(out.contains("huawei_ont_optical_present 1\n"));
+        assert!(out.contains("huawei_ont_rx_power -24.09\n"));
+        assert!(out.contains("huawei_ont_uptime_seconds 86400\n"));
+        assert!(out.contains("huawei_ont_sections_wan 0\n"));
+        assert!(!out.contains("serial_number"));
+
+        data.optical_present = false;
+        assert!(!encode_plain(&data).contains("rx_power"));
+    }
+
+    #[test]
+    fn test_freshness_collector_ages() {
+        let target = "http://freshness.test";
+        let scraped = SystemTime::now() - Duration::from_secs(30);
+        LAST_SUCCESS.lock().unwrap().insert(target.to_string(), scraped);
+
+        let mfs = FreshnessCollector::new().collect();
+        let age = |name: &str| {
+            let mf = mfs.iter().find(|mf| mf.name() == name).unwrap();
+            let m = mf.get_metric().iter().find(|m| m.get_label()[0].value() == target).unwrap();
+            m.get_gauge().value()
+        };
+        assert!(age("huawei_ont_metrics_age_seconds") >= 30.0);
+
+        LAST_SUCCESS.lock().unwrap().remove(target);
     }
 }
@@ -0,0 +1,194 @@
+use crate::parser::OntMetrics;
+use reqwest::Client;
+use serde::Serialize;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Posts a JSON payload to ALERT_WEBHOOK_URL when the scrape state changes, for
+/// setups without Alertmanager. Only transitions fire, never repeated states.
+pub struct Notifier {
+    client: Client,
+    url: String,
+    ont_url: String,
+    rx_threshold_dbm: Option<f64>,
+    state: State,
+}
+
+// Assumed healthy at startup, so an ONT that is already down alerts on the first scrape
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct State {
+    scrape_ok: bool,
+    link_up: bool,
+    rx_low: bool,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            scrape_ok: true,
+            link_up: true,
+            rx_low: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Event<'a> {
+    event: &'static str,
+    ont_url: &'a str,
+    detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rx_power_dbm: Option<f64>,
+}
+
+impl Notifier {
+    pub fn new(url: &str, ont_url: &str, rx_threshold_dbm: Option<f64>) -> Self {
+        Self {
+            client: Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("failed to build webhook client"),
+            url: url.to_string(),
+            ont_url: ont_url.to_string(),
+            rx_threshold_dbm,
+            state: State::default(),
+        }
+    }
+
+    /// Record the outcome of one scrape and post an event for every state change.
+    /// Delivery runs in the background so a slow webhook can't delay the scrape loop.
+    /// Failures within ONT_STARTUP_GRACE_SECS (`in_startup_grace`) are ignored, the same
+    /// as for huawei_ont_scrape_errors_total, so a booting ONT doesn't alert.
+    pub fn observe(&mut self, result: &anyhow::Result<OntMetrics>, in_startup_grace: bool) {
+        if in_startup_grace && result.is_err() {
+            return;
+        }
+        let (next, events) = transitions(self.state, result, self.rx_threshold_dbm, &self.ont_url);
+        self.state = next;
+
+        for event in events {
+            let body = match serde_json::to_value(&event) {
+                Ok(body) => body,
+                Err(e) => {
+                    warn!("Failed to encode webhook event: {}", e);
+                    continue;
+                }
+            };
+            let request = self.client.post(&self.url).json(&body);
+            let name = event.event;
+            tokio::spawn(async move {
+                match request.send().await.and_then(|resp| resp.error_for_status()) {
+                    Ok(_) => info!("Sent {} alert to webhook", name),
+                    Err(e) => warn!("Failed to send {} alert to webhook: {}", name, e),
+                }
+            });
+        }
+    }
+}
+
+// Link and rx state are only known from a successful scrape and are kept otherwise
+fn transitions<'a>(
+    prev: State,
+    result: &anyhow::Result<OntMetrics>,
+    rx_threshold_dbm: Option<f64>,
+    ont_url: &'a str,
+) -> (State, Vec<Event<'a>>) {
+    let mut next = prev;
+    let mut events = Vec::new();
+    let mut push = |event, detail: String, rx_power_dbm| {
+        events.push(Event { event, ont_url, detail, rx_power_dbm })
+    };
+
+    match result {
+        Err(e) => {
+            next.scrape_ok = false;
+            if prev.scrape_ok {
+                push("scrape_failing", format!("{:#}", e), None);
+            }
+        }
+        Ok(metrics) => {
+            next.scrape_ok = true;
+            if !prev.scrape_ok {
+                push("scrape_recovered", "Scrape succeeded again".to_string(), None);
+            }
+
+            next.link_up = metrics.optical_present;
+            match (prev.link_up, next.link_up) {
+                (true, false) => push("link_down", "Optical link reports no transceiver data".to_string(), None),
+                (false, true) => push("link_up", "Optical link is back".to_string(), Some(metrics.rx_power)),
+                _ => {}
+            }
+
+            if let Some(threshold) = rx_threshold_dbm
+                && metrics.optical_present
+                && !metrics.rx_power.is_nan()
+            {
+                next.rx_low = metrics.rx_power < threshold;
+                match (prev.rx_low, next.rx_low) {
+                    (false, true) => push(
+                        "rx_power_low",
+                        format!("RX power dropped below {} dBm", threshold),
+                        Some(metrics.rx_power),
+                    ),
+                    (true, false) => push(
+                        "rx_power_ok",
+                        format!("RX power back above {} dBm", threshold),
+                        Some(metrics.rx_power),
+                    ),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    (next, events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    fn names(events: &[Event]) -> Vec<&'static str> {
+        events.iter().map(|e| e.event).collect()
+    }
+
+    #[test]
+    fn test_transitions_fire_once() {
+        let ok = |rx_power, optical_present| {
+            Ok(OntMetrics { rx_power, optical_present, ..Default::default() })
+        };
+        let url = "http://ont";
+        let threshold = Some(-27.0);
+
+        let (state, events) = transitions(State::default(), &ok(-20.0, true), threshold, url);
+        assert!(events.is_empty());
+
+        let (state, events) = transitions(state, &Err(anyhow!("timeout")), threshold, url);
+        assert_eq!(names(&events), ["scrape_failing"]);
+        let (state, events) = transitions(state, &Err(anyhow!("timeout")), threshold, url);
+        assert!(events.is_empty());
+
+        let (state, events) = transitions(state, &ok(-28.0, true), threshold, url);
+        assert_eq!(names(&events), ["scrape_recovered", "rx_power_low"]);
+
+        let (state, events) = transitions(state, &ok(0.0, false), threshold, url);
+        assert_eq!(names(&events), ["link_down"]);
+
+        let (_, events) = transitions(state, &ok(-21.0, true), threshold, url);
+        assert_eq!(names(&events), ["link_up", "rx_power_ok"]);
+    }
+
+    #[test]
+    fn test_startup_grace_failures_ignored() {
+        let mut notifier = Notifier::new("http://127.0.0.1:9/hook", "http://ont", None);
+
+        notifier.observe(&Err(anyhow!("connection refused")), true);
+        notifier.observe(&Err(anyhow!("connection refused")), true);
+        assert_eq!(notifier.state, State::default());
+
+        // The first failure after the window still alerts
+        let (_, events) = transitions(notifier.state, &Err(anyhow!("timeout")), None, "http://ont");
+        assert_eq!(names(&events), ["scrape_failing"]);
+    }
+}
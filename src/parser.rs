@@ -1,14 +1,41 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use regex::Regex;
+use serde::Serialize;
+use std::collections::BTreeMap;
 
-#[derive(Debug, PartialEq, Default)]
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
 pub struct OntMetrics {
-    // Optical metrics
+    // Optical metrics (only meaningful when optical_present is set)
+    pub optical_present: bool,
     pub tx_power: f64,
     pub rx_power: f64,
     pub voltage: f64,
     pub bias_current: f64,
     pub temperature: f64,
+    pub rx_sensitivity: Option<f64>,
+    // Every module with readings on multi-PON units; the fields above are the selected one
+    pub optical_modules: Vec<OpticalModule>,
+    // Number of stOpticInfo arguments, to spot layouts that differ from the detected one
+    pub optical_field_count: Option<usize>,
+
+    // Nominal (reference) tx/rx power, only on firmware that reports it next to the measured values
+    pub tx_power_nominal: Option<f64>,
+    pub rx_power_nominal: Option<f64>,
+
+    // GPON error counters (optional, cumulative since device boot)
+    pub fec_corrected: Option<u64>,
+    pub bip_errors: Option<u64>,
+
+    // Rogue ONT / laser-always-on alarm (optional)
+    pub laser_alarm: Option<bool>,
+    // GPON loss-of-signal / loss-of-frame alarms (optional)
+    pub los_alarm: Option<bool>,
+    pub lof_alarm: Option<bool>,
+
+    // GPON registration identity (optional). The OLT only assigns an ONU ID once
+    // the ONT has registered, so it's absent while registration fails.
+    pub onu_id: Option<String>,
+    pub loid: Option<String>,
 
     // Device info metrics (optional)
     pub device_model: Option<String>,
@@ -16,25 +43,182 @@ pub struct OntMetrics {
     pub hardware_version: Option<String>,
     pub software_version: Option<String>,
     pub mac_address: Option<String>,
+    pub customization: Option<String>,
     pub uptime_seconds: Option<u64>,
+    pub cpu_usage_percent: Option<f64>,
+    pub memory_usage_percent: Option<f64>,
+    // Device clock, only on firmware that shows it
+    pub time_synced: Option<bool>,
+    pub device_time_seconds: Option<i64>,
 
-    // WAN/Internet metrics (optional)
+    // WAN/Internet metrics (optional), of the Internet connection on multi-WAN devices
     pub wan_status: Option<String>,
     pub wan_ip: Option<String>,
     pub wan_rx_bytes: Option<u64>,
     pub wan_tx_bytes: Option<u64>,
 
+    // Every WAN connection from the WAN page (Internet, VoIP, IPTV, ...)
+    pub wan_connections: Vec<WanConnection>,
+
     // LAN/WiFi metrics (optional)
     pub lan_clients_count: Option<u32>,
     pub wifi_clients_count: Option<u32>,
     pub total_clients_count: Option<u32>,
+    // Client count the LAN page declares itself (some firmware), to spot truncated lists
+    pub lan_clients_declared: Option<u32>,
+    // Remaining DHCP lease in seconds by client MAC (static-IP clients are absent)
+    pub client_leases: BTreeMap<String, u64>,
+    // Signal of each wireless client, for firmware that lists it (wired clients are absent)
+    pub client_rssi: Vec<ClientRssi>,
+
+    // Per-SSID WLAN configuration (empty unless the WLAN page was parsed)
+    pub wifi_ssids: Vec<WifiSsid>,
+
+    // Whether each optional section was fetched and parsed (absent if disabled)
+    pub sections: BTreeMap<&'static str, bool>,
+}
+
+// One stOpticInfo call; port is its position on the page ("0" on single-module devices)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OpticalModule {
+    pub port: String,
+    pub tx_power: f64,
+    pub rx_power: f64,
+    pub voltage: f64,
+    pub bias_current: f64,
+    pub temperature: f64,
+    pub tx_power_nominal: Option<f64>,
+    pub rx_power_nominal: Option<f64>,
+}
+
+impl OpticalModule {
+    // The readings of a single-module source (JSON API, diagnostic command)
+    fn single(metrics: &OntMetrics) -> Self {
+        Self {
+            port: "0".to_string(),
+            tx_power: metrics.tx_power,
+            rx_power: metrics.rx_power,
+            voltage: metrics.voltage,
+            bias_current: metrics.bias_current,
+            temperature: metrics.temperature,
+            tx_power_nominal: metrics.tx_power_nominal,
+            rx_power_nominal: metrics.rx_power_nominal,
+        }
+    }
+
+    fn has_values(&self) -> bool {
+        [self.tx_power, self.rx_power, self.voltage, self.temperature, self.bias_current]
+            .iter()
+            .any(|value| !value.is_nan())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WanConnection {
+    pub name: String,
+    // X_HW_SERVICELIST, e.g. INTERNET, VOIP, TR069_INTERNET
+    pub service: String,
+    pub status: Option<String>,
+    pub ip: Option<String>,
+    pub rx_bytes: Option<u64>,
+    pub tx_bytes: Option<u64>,
+    // LastConnectionError, e.g. ERROR_AUTHENTICATION_FAILURE after a PPPoE auth failure
+    pub last_error: Option<String>,
 }
 
-pub fn parse_ont_metrics(html: &str) -> Result<OntMetrics> {
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClientRssi {
+    pub mac: String,
+    // The ONT's port name for the SSID, e.g. "SSID1" (5 GHz is usually SSID5)
+    pub ssid: String,
+    pub rssi_dbm: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WifiSsid {
+    pub ssid: String,
+    pub enabled: bool,
+    pub security: String,
+    pub band: &'static str,
+}
+
+/// Per-device adjustments for how the optical fields are interpreted
+#[derive(Debug, Clone)]
+pub struct OpticalOptions {
+    // Multipliers from the raw field to mV / mA. Most firmware already reports
+    // mV and mA (1.0); SFF-8472 style raw values are 0.1 mV (0.1) and 2 µA (0.002).
+    pub voltage_scale: f64,
+    pub bias_scale: f64,
+    // Multiplier from the raw tx/rx power field to dBm: 1.0 for formatted decimals,
+    // 0.002 for the signed integer steps of the GPON diagnostics spec
+    pub power_scale: f64,
+    // Escape hatch for unknown firmware, applied on top of the detected layout
+    pub index_overrides: OpticalIndexOverrides,
+    // Which stOpticInfo call to read on pages with several (one per PON port, or a
+    // dummy template). None picks the first one whose LinkStatus is "ok".
+    pub instance: Option<usize>,
+}
+
+impl Default for OpticalOptions {
+    fn default() -> Self {
+        Self {
+            voltage_scale: 1.0,
+            bias_scale: 1.0,
+            power_scale: 1.0,
+            index_overrides: OpticalIndexOverrides::default(),
+            instance: None,
+        }
+    }
+}
+
+/// User-supplied stOpticInfo argument positions, each replacing the detected one
+#[derive(Debug, Clone, Default)]
+pub struct OpticalIndexOverrides {
+    pub tx: Option<usize>,
+    pub rx: Option<usize>,
+    pub voltage: Option<usize>,
+    pub temperature: Option<usize>,
+    pub bias: Option<usize>,
+    pub tx_nominal: Option<usize>,
+    pub rx_nominal: Option<usize>,
+}
+
+impl OpticalIndexOverrides {
+    fn apply(&self, layout: OpticalLayout) -> OpticalLayout {
+        OpticalLayout {
+            tx: self.tx.unwrap_or(layout.tx),
+            rx: self.rx.unwrap_or(layout.rx),
+            voltage: self.voltage.unwrap_or(layout.voltage),
+            temperature: self.temperature.unwrap_or(layout.temperature),
+            bias: self.bias.unwrap_or(layout.bias),
+            tx_nominal: self.tx_nominal.or(layout.tx_nominal),
+            rx_nominal: self.rx_nominal.or(layout.rx_nominal),
+        }
+    }
+}
+
+pub fn parse_ont_metrics(html: &str, options: &OpticalOptions) -> Result<OntMetrics> {
     let mut metrics = OntMetrics::default();
 
-    // Parse optical metrics
-    parse_optical_metrics(html, &mut metrics)?;
+    // Parse optical metrics. With the fiber unplugged some firmware renders the page
+    // without any stOpticInfo, which isn't an error: the other sections still apply.
+    if html.contains("new stOpticInfo(") {
+        parse_optical_metrics(html, options, &mut metrics)?;
+        metrics.optical_present = has_optical_values(&metrics);
+    }
+
+    // Try to parse GPON error counters if available
+    parse_gpon_counters(html, &mut metrics);
+
+    // Try to parse the rogue ONT alarm if available
+    metrics.laser_alarm = parse_laser_alarm(html);
+
+    // Try to parse the LOS/LOF alarms if available
+    metrics.los_alarm = parse_gpon_alarm(html, "LOS");
+    metrics.lof_alarm = parse_gpon_alarm(html, "LOF");
+
+    // Try to parse the GPON registration identity if available
+    parse_gpon_registration(html, &mut metrics);
 
     // Try to parse device info if available
     if let Ok(device_info) = parse_device_info(html) {
@@ -61,64 +245,428 @@ pub fn parse_ont_metrics(html: &str) -> Result<OntMetrics> {
     Ok(metrics)
 }
 
-fn parse_optical_metrics(html: &str, metrics: &mut OntMetrics) -> Result<()> {
+/// opticinfo.asp of an HG8145V5, with the escapes and padding the firmware emits
+pub const SAMPLE_OPTICAL_PAGE: &str = r#"
+var opticInfos = new Array(new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","\x202\x2e33","\x2d24\x2e09","3364","47","10","\x2d\x2d","\x2d\x2d","HUAWEI\x20\x20\x20\x20\x20\x20\x20\x20\x20","2416R080776AS\x20\x20","240529","1310","1490","20","0"),null);
+"#;
+
+/// Parse SAMPLE_OPTICAL_PAGE with the default options and check the values, to smoke-test
+/// a deployed binary without an ONT
+pub fn self_test() -> Result<OntMetrics> {
+    let metrics = parse_ont_metrics(SAMPLE_OPTICAL_PAGE, &OpticalOptions::default())?;
+    let expected = [
+        ("tx_power", metrics.tx_power, 2.33),
+        ("rx_power", metrics.rx_power, -24.09),
+        ("voltage", metrics.voltage, 3364.0),
+        ("temperature", metrics.temperature, 47.0),
+        ("bias_current", metrics.bias_current, 10.0),
+    ];
+    for (name, got, want) in expected {
+        if got != want {
+            bail!("Sample page parsed {} as {}, expected {}", name, got, want);
+        }
+    }
+    Ok(metrics)
+}
+
+// Key names the JSON API uses for each optical field, matched case-insensitively
+const JSON_TX_KEYS: &[&str] = &["TxPower", "TransOpticPower", "TxOpticalPower"];
+const JSON_RX_KEYS: &[&str] = &["RxPower", "RevOpticPower", "RxOpticalPower"];
+const JSON_VOLTAGE_KEYS: &[&str] = &["Voltage", "WorkVoltage", "SupplyVoltage"];
+const JSON_TEMPERATURE_KEYS: &[&str] = &["Temperature", "WorkTemperature"];
+const JSON_BIAS_KEYS: &[&str] = &["Bias", "BiasCurrent", "WorkBias"];
+const JSON_ONU_ID_KEYS: &[&str] = &["OnuId", "OntId", "ONU_ID"];
+const JSON_LOID_KEYS: &[&str] = &["LOID", "LoId"];
+const JSON_LOS_KEYS: &[&str] = &["LOS", "LosAlarm", "LosStatus", "LOS_Alarm"];
+const JSON_LOF_KEYS: &[&str] = &["LOF", "LofAlarm", "LofStatus", "LOF_Alarm"];
+
+/// Parse the optical data served as JSON by the web API of newer firmware
+/// (e.g. `/api/ntwk/wan?type=optic`). The fields may be nested anywhere in the document.
+pub fn parse_ont_metrics_json(body: &str, options: &OpticalOptions) -> Result<OntMetrics> {
+    let mut metrics = OntMetrics::default();
+
+    // Responses are often wrapped for XSSI protection, e.g. `while(1); /*{...}*/`
+    let start = body.find(['{', '[']).context("No JSON document in optical response")?;
+    let end = body.rfind(['}', ']']).context("No JSON document in optical response")?;
+    let value: serde_json::Value =
+        serde_json::from_str(&body[start..=end]).context("Invalid JSON in optical response")?;
+
+    let fields = [
+        JSON_TX_KEYS,
+        JSON_RX_KEYS,
+        JSON_VOLTAGE_KEYS,
+        JSON_TEMPERATURE_KEYS,
+        JSON_BIAS_KEYS,
+    ]
+    .map(|keys| find_json_field(&value, keys));
+
+    metrics.onu_id = find_json_field(&value, JSON_ONU_ID_KEYS).filter(|id| is_onu_id(id));
+    metrics.loid = find_json_field(&value, JSON_LOID_KEYS).filter(|loid| !loid.is_empty());
+    metrics.los_alarm = find_json_field(&value, JSON_LOS_KEYS).and_then(|v| parse_alarm_flag(&v));
+    metrics.lof_alarm = find_json_field(&value, JSON_LOF_KEYS).and_then(|v| parse_alarm_flag(&v));
+
+    // Same as a missing stOpticInfo: no transceiver data while the link is down
+    if fields.iter().all(Option::is_none) {
+        return Ok(metrics);
+    }
+
+    let number = |field: &Option<String>, name: &str| -> Result<f64> {
+        let raw = field.as_deref().with_context(|| format!("Missing {} in optical JSON", name))?;
+        parse_optical_field(raw).with_context(|| format!("Failed to parse {}", name))
+    };
+    metrics.tx_power = number(&fields[0], "TX Power")? * options.power_scale;
+    metrics.rx_power = number(&fields[1], "RX Power")? * options.power_scale;
+    metrics.voltage = number(&fields[2], "Voltage")? * options.voltage_scale;
+    metrics.temperature = number(&fields[3], "Temperature")?;
+    metrics.bias_current = number(&fields[4], "Bias Current")? * options.bias_scale;
+    metrics.optical_present = has_optical_values(&metrics);
+    if metrics.optical_present {
+        metrics.optical_modules = vec![OpticalModule::single(&metrics)];
+    }
+
+    Ok(metrics)
+}
+
+/// Parse the text output of the diagnostic optical command (e.g. `display optic`), one
+/// `Name(unit) : value` line per field. Values are read as printed (dBm, mV, mA, °C):
+/// the ONT_OPTICAL_* scales are for the web pages only.
+pub fn parse_diag_optic(text: &str) -> Result<OntMetrics> {
+    let mut metrics = OntMetrics::default();
+    let (mut tx, mut rx, mut voltage, mut bias, mut temperature) = (None, None, None, None, None);
+
+    for line in text.lines() {
+        let Some((name, value)) = line.split_once([':', '=']) else {
+            continue;
+        };
+        // "Tx optical power(dBm)" -> "txopticalpowerdbm"
+        let key: String = name.chars().filter(char::is_ascii_alphanumeric).collect::<String>().to_ascii_lowercase();
+        if ["threshold", "alarm", "warn"].iter().any(|skip| key.contains(skip)) {
+            continue;
+        }
+        let slot = match key.as_str() {
+            k if k.contains("power") && (k.starts_with("tx") || k.starts_with("transmit")) => &mut tx,
+            k if k.contains("power") && (k.starts_with("rx") || k.starts_with("receive")) => &mut rx,
+            k if k.contains("voltage") => &mut voltage,
+            k if k.contains("bias") => &mut bias,
+            k if k.contains("temperature") => &mut temperature,
+            _ => continue,
+        };
+        if slot.is_none() {
+            *slot = value.split_whitespace().next().and_then(|v| parse_optical_field(v).ok());
+        }
+    }
+
+    if tx.is_none() && rx.is_none() {
+        bail!("No optical power in diagnostic command output");
+    }
+    metrics.tx_power = tx.unwrap_or(f64::NAN);
+    metrics.rx_power = rx.unwrap_or(f64::NAN);
+    metrics.voltage = voltage.unwrap_or(f64::NAN);
+    metrics.bias_current = bias.unwrap_or(f64::NAN);
+    metrics.temperature = temperature.unwrap_or(f64::NAN);
+    metrics.optical_present = has_optical_values(&metrics);
+    if metrics.optical_present {
+        metrics.optical_modules = vec![OpticalModule::single(&metrics)];
+    }
+
+    // The command output often carries the GPON counters and alarms as well
+    parse_gpon_counters(text, &mut metrics);
+    metrics.laser_alarm = parse_laser_alarm(text);
+    metrics.los_alarm = parse_gpon_alarm(text, "LOS");
+    metrics.lof_alarm = parse_gpon_alarm(text, "LOF");
+
+    Ok(metrics)
+}
+
+// Depth-first search for the first of `keys`, as a string or number
+fn find_json_field(value: &serde_json::Value, keys: &[&str]) -> Option<String> {
+    match value {
+        serde_json::Value::Object(map) => {
+            let direct = map.iter().find_map(|(k, v)| {
+                if !keys.iter().any(|key| key.eq_ignore_ascii_case(k)) {
+                    return None;
+                }
+                match v {
+                    serde_json::Value::String(s) => Some(s.trim().to_string()),
+                    serde_json::Value::Number(n) => Some(n.to_string()),
+                    _ => None,
+                }
+            });
+            direct.or_else(|| map.values().find_map(|v| find_json_field(v, keys)))
+        }
+        serde_json::Value::Array(items) => items.iter().find_map(|v| find_json_field(v, keys)),
+        _ => None,
+    }
+}
+
+/// Positions of the optical fields within the stOpticInfo arguments
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpticalLayout {
+    pub tx: usize,
+    pub rx: usize,
+    pub voltage: usize,
+    pub temperature: usize,
+    pub bias: usize,
+    // Nominal power positions, for layouts that carry them
+    pub tx_nominal: Option<usize>,
+    pub rx_nominal: Option<usize>,
+}
+
+// HG8145V5 and most models:
+// stOpticInfo(domain, LinkStatus, transOpticPower, revOpticPower, voltage, temperature, bias, ...)
+pub const DEFAULT_LAYOUT: OpticalLayout = OpticalLayout {
+    tx: 2,
+    rx: 3,
+    voltage: 4,
+    temperature: 5,
+    bias: 6,
+    tx_nominal: None,
+    rx_nominal: None,
+};
+
+// EG8145V5 renders a longer array with the nominal (reference) tx/rx power at 2/3
+// and the measured values after them:
+// stOpticInfo(domain, LinkStatus, txNominal, rxNominal, transOpticPower, revOpticPower, voltage, temperature, bias, ...)
+pub const EG8145V5_LAYOUT: OpticalLayout = OpticalLayout {
+    tx: 4,
+    rx: 5,
+    voltage: 6,
+    temperature: 7,
+    bias: 8,
+    tx_nominal: Some(2),
+    rx_nominal: Some(3),
+};
+
+// The EG8145V5 array has at least this many fields, the HG8145V5 one has 16
+const EG8145V5_MIN_FIELDS: usize = 20;
+
+fn detect_layout(field_count: usize) -> OpticalLayout {
+    if field_count >= EG8145V5_MIN_FIELDS {
+        EG8145V5_LAYOUT
+    } else {
+        DEFAULT_LAYOUT
+    }
+}
+
+fn parse_optical_metrics(html: &str, options: &OpticalOptions, metrics: &mut OntMetrics) -> Result<()> {
     // Look for: new stOpticInfo(..., "2.33", "-24.09", "3364", "47", "10", ...)
-    // function definition: stOpticInfo(domain, LinkStatus, transOpticPower, revOpticPower, voltage, temperature, bias, ...)
-    // Indices (0-based):
-    // 2: transOpticPower (TX)
-    // 3: revOpticPower (RX)
-    // 4: voltage
-    // 5: temperature
-    // 6: bias
+    // The positions of the fields depend on the firmware, see detect_layout()
 
     let re = Regex::new(r"new stOpticInfo\(([^)]+)\)").unwrap();
-    let caps = re
+
+    // Split arguments by comma, keeping commas inside quoted strings (e.g. "-24,09")
+    let instances: Vec<Vec<&str>> = re
+        .captures_iter(html)
+        .map(|caps| split_js_args(caps.get(1).unwrap().as_str()))
+        .collect();
+    let link_ok = |args: &[&str]| {
+        args.get(1)
+            .is_some_and(|status| status.trim().trim_matches('"').eq_ignore_ascii_case("ok"))
+    };
+    let selected = match options.instance {
+        Some(n) if n >= instances.len() => {
+            bail!("ONT_OPTICAL_INSTANCE={} but the page has {} stOpticInfo calls", n, instances.len())
+        }
+        Some(n) => n,
+        None if instances.is_empty() => bail!("Failed to find stOpticInfo call"),
+        None => instances.iter().position(|args| link_ok(args)).unwrap_or(0),
+    };
+    let args = &instances[selected];
+    metrics.optical_field_count = Some(args.len());
+
+    let module = parse_optical_module(args, options, selected)?;
+    metrics.tx_power = module.tx_power;
+    metrics.rx_power = module.rx_power;
+    metrics.voltage = module.voltage;
+    metrics.temperature = module.temperature;
+    metrics.bias_current = module.bias_current;
+    metrics.tx_power_nominal = module.tx_power_nominal;
+    metrics.rx_power_nominal = module.rx_power_nominal;
+
+    // The other modules of multi-PON units, skipping dummy templates and unparsable calls
+    metrics.optical_modules = instances
+        .iter()
+        .enumerate()
+        .filter_map(|(port, other)| {
+            if port == selected {
+                Some(module.clone())
+            } else if link_ok(other) {
+                parse_optical_module(other, options, port).ok()
+            } else {
+                None
+            }
+        })
+        .filter(OpticalModule::has_values)
+        .collect();
+
+    // Receiver sensitivity floor, only rendered by some firmware
+    metrics.rx_sensitivity = Regex::new(r#"RxSensitivity["']?\s*[=:]\s*["']?(-?\d+(?:\.\d+)?)"#)
+        .unwrap()
         .captures(html)
-        .context("Failed to find stOpticInfo call")?;
-    let args_str = caps.get(1).unwrap().as_str();
+        .and_then(|caps| caps.get(1))
+        .and_then(|m| m.as_str().parse::<f64>().ok());
 
-    // Split arguments by comma, considering they are quoted strings.
-    // A simple split matches the example format sufficiently.
-    let args: Vec<&str> = args_str.split(',').collect();
+    Ok(())
+}
 
-    if args.len() < 7 {
-        return Err(anyhow::anyhow!("Not enough arguments in stOpticInfo call"));
+fn parse_optical_module(args: &[&str], options: &OpticalOptions, port: usize) -> Result<OpticalModule> {
+    let layout = options.index_overrides.apply(detect_layout(args.len()));
+    let highest = [layout.tx, layout.rx, layout.voltage, layout.temperature, layout.bias]
+        .into_iter()
+        .max()
+        .unwrap();
+    if args.len() <= highest {
+        return Err(anyhow::anyhow!(
+            "Not enough arguments in stOpticInfo call ({} fields, index {} needed)",
+            args.len(),
+            highest
+        ));
     }
 
-    // Helper to clean quotes and decode hex escapes
-    let clean_arg = |s: &str| -> String {
-        let s = s.trim().trim_matches('"');
-        decode_hex_escapes(s)
+    let tx_power_str = clean_js_arg(args[layout.tx]);
+    let rx_power_str = clean_js_arg(args[layout.rx]);
+    let voltage_str = clean_js_arg(args[layout.voltage]);
+    let temperature_str = clean_js_arg(args[layout.temperature]);
+    let bias_str = clean_js_arg(args[layout.bias]);
+
+    // Nominal values are informational, a missing or unparsable one doesn't fail the scrape
+    let nominal = |index: Option<usize>| {
+        index
+            .and_then(|i| args.get(i))
+            .and_then(|arg| parse_number(&clean_js_arg(arg)).ok())
+            .map(|value| value * options.power_scale)
     };
 
-    let tx_power_str = clean_arg(args[2]);
-    let rx_power_str = clean_arg(args[3]);
-    let voltage_str = clean_arg(args[4]);
-    let temperature_str = clean_arg(args[5]);
-    let bias_str = clean_arg(args[6]);
-
-    metrics.tx_power = tx_power_str
-        .trim()
-        .parse::<f64>()
-        .context("Failed to parse TX Power")?;
-    metrics.rx_power = rx_power_str
-        .trim()
-        .parse::<f64>()
-        .context("Failed to parse RX Power")?;
-    metrics.voltage = voltage_str
-        .trim()
-        .parse::<f64>()
-        .context("Failed to parse Voltage")?;
-    metrics.temperature = temperature_str
-        .trim()
-        .parse::<f64>()
-        .context("Failed to parse Temperature")?;
-    metrics.bias_current = bias_str
-        .trim()
-        .parse::<f64>()
-        .context("Failed to parse Bias Current")?;
+    Ok(OpticalModule {
+        port: port.to_string(),
+        tx_power: parse_optical_field(&tx_power_str).context("Failed to parse TX Power")? * options.power_scale,
+        rx_power: parse_optical_field(&rx_power_str).context("Failed to parse RX Power")? * options.power_scale,
+        voltage: parse_optical_field(&voltage_str).context("Failed to parse Voltage")? * options.voltage_scale,
+        temperature: parse_optical_field(&temperature_str).context("Failed to parse Temperature")?,
+        bias_current: parse_optical_field(&bias_str).context("Failed to parse Bias Current")? * options.bias_scale,
+        tx_power_nominal: nominal(layout.tx_nominal),
+        rx_power_nominal: nominal(layout.rx_nominal),
+    })
+}
 
-    Ok(())
+// Clean quotes and decode hex escapes of one stOpticInfo argument
+fn clean_js_arg(s: &str) -> String {
+    decode_hex_escapes(s.trim().trim_matches('"'))
+}
+
+// Split a JS argument list on commas that aren't inside a double-quoted string
+pub fn split_js_args(s: &str) -> Vec<&str> {
+    let mut args = Vec::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut start = 0;
+
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => in_quotes = !in_quotes,
+            ',' if !in_quotes => {
+                args.push(&s[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    args.push(&s[start..]);
+    args
+}
+
+// Firmware renders "--" for a value the transceiver doesn't report (e.g. tx power
+// while the laser is off). That field becomes NaN instead of failing the parse.
+fn parse_optical_field(s: &str) -> Result<f64> {
+    match s.trim() {
+        "--" | "" => Ok(f64::NAN),
+        value => parse_number(value),
+    }
+}
+
+// All core fields "--" is just another way of saying the link is down
+fn has_optical_values(metrics: &OntMetrics) -> bool {
+    [metrics.tx_power, metrics.rx_power, metrics.voltage, metrics.temperature, metrics.bias_current]
+        .iter()
+        .any(|value| !value.is_nan())
+}
+
+// Parse a numeric field, accepting a comma as decimal separator (localized firmware)
+fn parse_number(s: &str) -> Result<f64> {
+    let s = s.trim();
+    let is_comma_decimal = s.matches(',').count() == 1
+        && s.chars().all(|c| c.is_ascii_digit() || c == ',' || c == '-' || c == '+');
+
+    if is_comma_decimal {
+        Ok(s.replace(',', ".").parse::<f64>()?)
+    } else {
+        Ok(s.parse::<f64>()?)
+    }
+}
+
+fn parse_gpon_counters(html: &str, metrics: &mut OntMetrics) {
+    // Debug pages render these as e.g. FECCorrectedCodewords = "123" or BIPErrors: 4
+    metrics.fec_corrected = Regex::new(r#"(?i)FEC_?Corrected\w*["']?\s*[=:]\s*["']?(\d+)"#)
+        .unwrap()
+        .captures(html)
+        .and_then(|caps| caps.get(1))
+        .and_then(|m| m.as_str().parse::<u64>().ok());
+
+    metrics.bip_errors = Regex::new(r#"(?i)BIP_?Err\w*["']?\s*[=:]\s*["']?(\d+)"#)
+        .unwrap()
+        .captures(html)
+        .and_then(|caps| caps.get(1))
+        .and_then(|m| m.as_str().parse::<u64>().ok());
+}
+
+fn parse_gpon_registration(html: &str, metrics: &mut OntMetrics) {
+    // e.g. var ontid = "1"; or OnuId: 1, and var LOID = "12345678"
+    metrics.onu_id = Regex::new(r#"(?i)\b(?:ONU|ONT)_?ID["']?\s*[=:]\s*["']?([^"',;\s]*)"#)
+        .unwrap()
+        .captures(html)
+        .map(|caps| decode_field_escapes(&caps[1]).trim().to_string())
+        .filter(|id| is_onu_id(id));
+
+    metrics.loid = Regex::new(r#"(?i)\bLOID["']?\s*[=:]\s*["']([^"']*)["']"#)
+        .unwrap()
+        .captures(html)
+        .map(|caps| decode_field_escapes(&caps[1]).trim().to_string())
+        .filter(|loid| !loid.is_empty());
+}
+
+// Unregistered ONTs show a placeholder such as "--" or an empty string
+fn is_onu_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_digit())
+}
+
+fn parse_laser_alarm(html: &str) -> Option<bool> {
+    // e.g. LaserAlwaysOn = "1" or RogueOntStatus: "Yes"
+    let value = Regex::new(r#"(?i)(?:LaserAlwaysOn|RogueOn[tu])\w*["']?\s*[=:]\s*["']?(\w+)"#)
+        .unwrap()
+        .captures(html)?
+        .get(1)?
+        .as_str()
+        .to_ascii_lowercase();
+
+    parse_alarm_flag(&value)
+}
+
+// e.g. LOS = "1", LosAlarm: "Yes" or LOF_Status = "Normal"; a bare word so that
+// names such as PacketLoss don't match
+fn parse_gpon_alarm(html: &str, alarm: &str) -> Option<bool> {
+    let pattern = format!(r#"(?i)\b{}(?:_?(?:Alarm|Status|State|Flag))?["']?\s*[=:]\s*["']?(\w+)"#, alarm);
+    let value = Regex::new(&pattern).unwrap().captures(html)?.get(1)?.as_str().to_string();
+    parse_alarm_flag(&value)
+}
+
+fn parse_alarm_flag(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" | "alarm" => Some(true),
+        "0" | "false" | "no" | "off" | "normal" => Some(false),
+        _ => None,
+    }
 }
 
 #[derive(Debug, Default)]
@@ -137,7 +685,7 @@ fn parse_device_info(html: &str) -> Result<DeviceInfo> {
         .unwrap()
         .captures(html)
     {
-        info.model = Some(caps.get(1).unwrap().as_str().to_string());
+        info.model = Some(caps[1].trim().to_string());
     }
 
     // Try to find serial number
@@ -145,7 +693,7 @@ fn parse_device_info(html: &str) -> Result<DeviceInfo> {
         .unwrap()
         .captures(html)
     {
-        info.serial = Some(caps.get(1).unwrap().as_str().to_string());
+        info.serial = Some(caps[1].trim().to_string());
     }
 
     // Try to find software version
@@ -153,7 +701,7 @@ fn parse_device_info(html: &str) -> Result<DeviceInfo> {
         .unwrap()
         .captures(html)
     {
-        info.version = Some(caps.get(1).unwrap().as_str().to_string());
+        info.version = Some(caps[1].trim().to_string());
     }
 
     // Try to find uptime (various formats)
@@ -164,13 +712,9 @@ fn parse_device_info(html: &str) -> Result<DeviceInfo> {
         .and_then(|caps| caps.get(1))
         .and_then(|m| m.as_str().parse::<u64>().ok());
 
-    // Format 2: UpTime="12345"
+    // Format 2: UpTime="12345" or UpTime: "1 day, 3:04:05"
     if info.uptime.is_none() {
-        info.uptime = Regex::new(r#"UpTime[=:]\s*["']?(\d+)["']?"#)
-            .unwrap()
-            .captures(html)
-            .and_then(|caps| caps.get(1))
-            .and_then(|m| m.as_str().parse::<u64>().ok());
+        info.uptime = find_uptime_field(html);
     }
 
     Ok(info)
@@ -273,17 +817,209 @@ pub fn decode_hex_escapes(s: &str) -> String {
     output
 }
 
+/// Value of an `UpTime = ...` / `UpTime: "..."` assignment, normalized to seconds
+pub fn find_uptime_field(html: &str) -> Option<u64> {
+    Regex::new(r#"UpTime["']?\s*[=:]\s*(?:["']([^"']+)["']|(\d+))"#)
+        .unwrap()
+        .captures(html)
+        .and_then(|caps| caps.get(1).or(caps.get(2)))
+        .and_then(|m| parse_uptime(m.as_str()))
+}
+
+/// Uptime from a dedicated time/uptime endpoint, served as JSON (e.g.
+/// `{"UpTime": 98765}`) or as a script assignment like the device info page
+pub fn parse_uptime_page(body: &str) -> Option<u64> {
+    let json = body
+        .find(['{', '['])
+        .zip(body.rfind(['}', ']']))
+        .filter(|(start, end)| start < end)
+        .and_then(|(start, end)| serde_json::from_str::<serde_json::Value>(&body[start..=end]).ok());
+    if let Some(value) = json {
+        return find_json_field(&value, &["UpTime", "SysUpTime", "SystemUpTime"]).and_then(|raw| parse_uptime(&raw));
+    }
+    find_uptime_field(body)
+}
+
+/// Normalizes the uptime formats found across models to seconds: bare seconds
+/// ("98765"), colon-separated ("3:04:05", "01:03:04:05" with days first) and
+/// textual ("1 day, 3:04:05", "2 days 03:04:05", "1d 3h 4m 5s", "3 hours 4 minutes").
+pub fn parse_uptime(s: &str) -> Option<u64> {
+    const DAY: u64 = 86400;
+    let s = s.trim();
+    if let Ok(seconds) = s.parse::<u64>() {
+        return Some(seconds);
+    }
+
+    let tokens: Vec<&str> = s
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|t| !t.is_empty())
+        .collect();
+    if tokens.is_empty() {
+        return None;
+    }
+
+    let mut total = 0;
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = tokens[i];
+        i += 1;
+
+        if token.contains(':') {
+            // [[d:]h:]m:s
+            let parts: Vec<u64> = token.split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+            if !(2..=4).contains(&parts.len()) {
+                return None;
+            }
+            let weights = [DAY, 3600, 60, 1];
+            total += parts
+                .iter()
+                .zip(&weights[4 - parts.len()..])
+                .map(|(value, weight)| value * weight)
+                .sum::<u64>();
+            continue;
+        }
+
+        // A number followed by its unit, attached ("3h") or as the next token ("3 hours")
+        let digits = token.find(|c: char| !c.is_ascii_digit()).unwrap_or(token.len());
+        let value: u64 = token[..digits].parse().ok()?;
+        let unit = if digits < token.len() {
+            &token[digits..]
+        } else {
+            i += 1;
+            tokens.get(i - 1)?
+        };
+        let weight = match unit.to_ascii_lowercase().as_str() {
+            "d" | "day" | "days" => DAY,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3600,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60,
+            "s" | "sec" | "secs" | "second" | "seconds" => 1,
+            _ => return None,
+        };
+        total += value * weight;
+    }
+    Some(total)
+}
+
+/// Unix time of a "2024-05-01 12:34:56" / "2024-05-01T12:34:56Z" / "...+08:00"
+/// timestamp. Without an offset the device's local time is taken as UTC.
+pub fn parse_datetime(s: &str) -> Option<i64> {
+    let caps = Regex::new(r"^(\d{4})-(\d{2})-(\d{2})[T ](\d{2}):(\d{2}):(\d{2})(?:\.\d+)?\s*(Z|[+-]\d{2}:?\d{2})?$")
+        .unwrap()
+        .captures(s.trim())?;
+    let field = |i: usize| caps[i].parse::<i64>().ok();
+    let (year, month, day) = (field(1)?, field(2)?, field(3)?);
+    let (hour, minute, second) = (field(4)?, field(5)?, field(6)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    // Days since 1970-01-01 in the proleptic Gregorian calendar (Howard Hinnant's days_from_civil)
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let offset = match caps.get(7).map(|m| m.as_str()) {
+        None | Some("Z") => 0,
+        Some(tz) => {
+            let digits = tz[1..].replace(':', "");
+            let minutes = digits[..2].parse::<i64>().ok()? * 60 + digits[2..].parse::<i64>().ok()?;
+            if tz.starts_with('-') { -minutes * 60 } else { minutes * 60 }
+        }
+    };
+    Some(days * 86400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// Decodes `\xHH` and `%HH` escapes in a single pass, for string fields that some
+/// firmware hex-escapes and other firmware URL-encodes. Decoding once means an
+/// escaped `%` or `\` is never decoded a second time. Escapes are UTF-8 bytes, so
+/// `%E4%B8%AD` decodes to one character rather than three Latin-1 ones.
+pub fn decode_field_escapes(s: &str) -> String {
+    let mut output = Vec::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(c) = rest.chars().next() {
+        let (hex, len) = match c {
+            '\\' if rest[1..].starts_with('x') => (rest.get(2..4), 4),
+            '%' => (rest.get(1..3), 3),
+            _ => (None, 0),
+        };
+        match hex
+            .filter(|h| h.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|h| u8::from_str_radix(h, 16).ok())
+        {
+            Some(byte) => {
+                output.push(byte);
+                rest = &rest[len..];
+            }
+            None => {
+                output.extend_from_slice(&rest.as_bytes()[..c.len_utf8()]);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    String::from_utf8_lossy(&output).into_owned()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_parse_uptime() {
+        assert_eq!(parse_uptime("98765"), Some(98765));
+        assert_eq!(parse_uptime("1 day, 3:04:05"), Some(86400 + 3 * 3600 + 4 * 60 + 5));
+        assert_eq!(parse_uptime("2 days 03:04:05"), Some(2 * 86400 + 11045));
+        assert_eq!(parse_uptime("01:03:04:05"), Some(86400 + 11045));
+        assert_eq!(parse_uptime("1d 3h 4m 5s"), Some(86400 + 11045));
+        assert_eq!(parse_uptime("3 hours 4 minutes"), Some(11040));
+        assert_eq!(parse_uptime("--"), None);
+        assert_eq!(parse_uptime(""), None);
+
+        assert_eq!(find_uptime_field(r#"UpTime: "1 day, 0:00:10""#), Some(86410));
+        assert_eq!(find_uptime_field("var UpTime=3600;"), Some(3600));
+    }
+
+    #[test]
+    fn test_parse_uptime_page() {
+        assert_eq!(parse_uptime_page(r#"{"UpTime": 98765}"#), Some(98765));
+        assert_eq!(parse_uptime_page(r#"while(1); /*{"data": {"sysUpTime": "1 day, 3:04:05"}}*/"#), Some(97445));
+        assert_eq!(parse_uptime_page(r#"var UpTime = "3600";"#), Some(3600));
+        assert_eq!(parse_uptime_page(r#"{"CurrentTime": "2024-01-01 00:00:00"}"#), None);
+    }
+
+    #[test]
+    fn test_parse_datetime() {
+        assert_eq!(parse_datetime("1970-01-01 00:00:00"), Some(0));
+        assert_eq!(parse_datetime("2024-05-01T12:34:56Z"), Some(1714566896));
+        assert_eq!(parse_datetime("2024-05-01 20:34:56+08:00"), Some(1714566896));
+        assert_eq!(parse_datetime("2000-02-29 00:00:00"), Some(951782400));
+        assert_eq!(parse_datetime("2024-13-01 00:00:00"), None);
+        assert_eq!(parse_datetime("May 1 2024"), None);
+    }
+
+    #[test]
+    fn test_decode_field_escapes() {
+        assert_eq!(decode_field_escapes(r"HG8145V5%20\x2dA"), "HG8145V5 -A");
+        assert_eq!(decode_field_escapes("V5R020C10S254%2e1"), "V5R020C10S254.1");
+        // An encoded escape character is not decoded twice
+        assert_eq!(decode_field_escapes(r"100%25\x2520"), "100%%20");
+        assert_eq!(decode_field_escapes("50% off %zz"), "50% off %zz");
+        // Multi-byte UTF-8, also split across both escape styles
+        assert_eq!(decode_field_escapes("%E4%B8%AD"), "中");
+        assert_eq!(decode_field_escapes(r"Caf%C3\xa9 中%E6%96%87"), "Café 中文");
+        assert_eq!(decode_field_escapes("%FF"), "\u{FFFD}");
+    }
+
     #[test]
     fn test_parse_metrics_js() {
         let html = r#"
         var opticInfos = new Array(new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","\x202\x2e33","\x2d24\x2e09","3364","47","10","\x2d\x2d","\x2d\x2d","HUAWEI\x20\x20\x20\x20\x20\x20\x20\x20\x20","2416R080776AS\x20\x20","240529","1310","1490","20","0"),null);
         "#;
 
-        let metrics = parse_ont_metrics(html).unwrap();
+        let metrics = parse_ont_metrics(html, &OpticalOptions::default()).unwrap();
 
         assert_eq!(metrics.tx_power, 2.33);
         assert_eq!(metrics.rx_power, -24.09);
@@ -292,6 +1028,328 @@ mod tests {
         assert_eq!(metrics.bias_current, 10.0);
     }
 
+    #[test]
+    fn test_self_test() {
+        let metrics = self_test().unwrap();
+        assert!(metrics.optical_present);
+        assert_eq!(metrics.rx_power, -24.09);
+
+        // /selftest returns the parsed values as JSON
+        let json = serde_json::to_value(&metrics).unwrap();
+        assert_eq!(json["tx_power"], 2.33);
+        assert_eq!(json["rx_power"], -24.09);
+        assert_eq!(json["optical_modules"][0]["port"], "0");
+    }
+
+    #[test]
+    fn test_parse_metrics_multiple_instances() {
+        let html = r#"
+        var opticInfos = new Array(new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","","0","0","0","0","0"),
+        new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","2.33","-24.09","3364","47","10"),null);
+        "#;
+
+        let metrics = parse_ont_metrics(html, &OpticalOptions::default()).unwrap();
+        assert_eq!(metrics.rx_power, -24.09);
+        assert_eq!(metrics.tx_power, 2.33);
+
+        let first = OpticalOptions { instance: Some(0), ..Default::default() };
+        assert_eq!(parse_ont_metrics(html, &first).unwrap().rx_power, 0.0);
+
+        let missing = OpticalOptions { instance: Some(2), ..Default::default() };
+        assert!(parse_ont_metrics(html, &missing).is_err());
+    }
+
+    #[test]
+    fn test_parse_metrics_multiple_modules() {
+        let html = r#"
+        var opticInfos = new Array(new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","2.33","-24.09","3364","47","10"),
+        new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","","0","0","0","0","0"),
+        new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","2.10","-20.50","3300","51","12"),null);
+        "#;
+
+        let metrics = parse_ont_metrics(html, &OpticalOptions::default()).unwrap();
+        assert_eq!(metrics.rx_power, -24.09);
+        let ports: Vec<(&str, f64, f64)> =
+            metrics.optical_modules.iter().map(|m| (m.port.as_str(), m.rx_power, m.temperature)).collect();
+        assert_eq!(ports, [("0", -24.09, 47.0), ("2", -20.5, 51.0)]);
+
+        let single = parse_ont_metrics(SAMPLE_OPTICAL_PAGE, &OpticalOptions::default()).unwrap();
+        assert_eq!(single.optical_modules.len(), 1);
+        assert_eq!(single.optical_modules[0].port, "0");
+    }
+
+    #[test]
+    fn test_parse_metrics_missing_values() {
+        let html = r#"var opticInfos = new Array(new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","\x2d\x2d","-24.09","3364","47","10"),null);"#;
+        let metrics = parse_ont_metrics(html, &OpticalOptions::default()).unwrap();
+        assert!(metrics.optical_present);
+        assert!(metrics.tx_power.is_nan());
+        assert_eq!(metrics.rx_power, -24.09);
+
+        let html = r#"new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","--","--","--","--","--")"#;
+        let metrics = parse_ont_metrics(html, &OpticalOptions::default()).unwrap();
+        assert!(!metrics.optical_present);
+    }
+
+    #[test]
+    fn test_parse_metrics_link_down() {
+        let html = r#"
+        var opticInfos = new Array(null);
+        var FECCorrectedCodewords = "1024";
+        "#;
+
+        let metrics = parse_ont_metrics(html, &OpticalOptions::default()).unwrap();
+        assert!(!metrics.optical_present);
+        assert_eq!(metrics.fec_corrected, Some(1024));
+
+        let truncated = r#"new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok")"#;
+        assert!(parse_ont_metrics(truncated, &OpticalOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_parse_metrics_json() {
+        let body = r#"while(1); /*{"errcode":0,"data":{"optic":{"TxPower":"2.33","RxPower":"-24.09","Voltage":3364,"Temperature":"47","BiasCurrent":"10"}}}*/"#;
+
+        let metrics = parse_ont_metrics_json(body, &OpticalOptions::default()).unwrap();
+        assert!(metrics.optical_present);
+        assert_eq!(metrics.tx_power, 2.33);
+        assert_eq!(metrics.rx_power, -24.09);
+        assert_eq!(metrics.voltage, 3364.0);
+        assert_eq!(metrics.temperature, 47.0);
+        assert_eq!(metrics.bias_current, 10.0);
+
+        let linkdown = parse_ont_metrics_json(r#"{"errcode":0,"data":{}}"#, &OpticalOptions::default()).unwrap();
+        assert!(!linkdown.optical_present);
+    }
+
+    #[test]
+    fn test_parse_diag_optic() {
+        let text = "
+        display optic
+        Voltage(mV)                : 3310
+        Bias(mA)                   : 12
+        Rx power upper threshold   : -8.00
+        Tx optical power(dBm)      : 2.12
+        Rx optical power(dBm)      : -19.87 dBm
+        Temperature(C)             : 52
+        FECCorrectedCodewords      : 17
+        ";
+
+        let metrics = parse_diag_optic(text).unwrap();
+        assert!(metrics.optical_present);
+        assert_eq!(metrics.tx_power, 2.12);
+        assert_eq!(metrics.rx_power, -19.87);
+        assert_eq!(metrics.voltage, 3310.0);
+        assert_eq!(metrics.bias_current, 12.0);
+        assert_eq!(metrics.temperature, 52.0);
+        assert_eq!(metrics.fec_corrected, Some(17));
+        assert_eq!(metrics.optical_modules.len(), 1);
+
+        assert!(parse_diag_optic("Error: unknown command").is_err());
+    }
+
+    #[test]
+    fn test_parse_laser_alarm() {
+        assert_eq!(parse_laser_alarm(r#"var LaserAlwaysOn = "1";"#), Some(true));
+        assert_eq!(parse_laser_alarm(r#"RogueOntStatus: "Normal""#), Some(false));
+        assert_eq!(parse_laser_alarm(r#"var LaserAlwaysOn = "--";"#), None);
+        // Firmware without the field
+        assert_eq!(parse_laser_alarm(r#"var opticInfos = new Array(null);"#), None);
+    }
+
+    #[test]
+    fn test_parse_gpon_alarm() {
+        let html = r#"var LosStatus = "1"; var LOF_Alarm = "Normal"; var PacketLoss = "0";"#;
+        assert_eq!(parse_gpon_alarm(html, "LOS"), Some(true));
+        assert_eq!(parse_gpon_alarm(html, "LOF"), Some(false));
+        assert_eq!(parse_gpon_alarm(r#"var PacketLoss = "1";"#, "LOS"), None);
+
+        let body = r#"{"data":{"LosAlarm":0,"LofAlarm":"1"}}"#;
+        let metrics = parse_ont_metrics_json(body, &OpticalOptions::default()).unwrap();
+        assert_eq!(metrics.los_alarm, Some(false));
+        assert_eq!(metrics.lof_alarm, Some(true));
+    }
+
+    #[test]
+    fn test_parse_gpon_registration() {
+        let html = r#"var ontid = "3"; var LOID = "\x31234567";"#;
+        let metrics = parse_ont_metrics(html, &OpticalOptions::default()).unwrap();
+        assert_eq!(metrics.onu_id.as_deref(), Some("3"));
+        assert_eq!(metrics.loid.as_deref(), Some("1234567"));
+
+        let html = r#"var ontid = "--"; var LOID = "";"#;
+        let metrics = parse_ont_metrics(html, &OpticalOptions::default()).unwrap();
+        assert_eq!(metrics.onu_id, None);
+        assert_eq!(metrics.loid, None);
+
+        let json = r#"{"optic": {"TxPower": "2.1", "RxPower": "-20", "Voltage": "3300", "Temperature": "40", "Bias": "9", "OnuId": 5, "LOID": "abc"}}"#;
+        let metrics = parse_ont_metrics_json(json, &OpticalOptions::default()).unwrap();
+        assert_eq!(metrics.onu_id.as_deref(), Some("5"));
+        assert_eq!(metrics.loid.as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn test_parse_gpon_counters() {
+        let html = r#"
+        var opticInfos = new Array(new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","2.33","-24.09","3364","47","10"),null);
+        var FECCorrectedCodewords = "1024";
+        var BIPErrors = "7";
+        "#;
+
+        let metrics = parse_ont_metrics(html, &OpticalOptions::default()).unwrap();
+        assert_eq!(metrics.fec_corrected, Some(1024));
+        assert_eq!(metrics.bip_errors, Some(7));
+
+        // Pages without the counters leave them unset rather than 0
+        let mut without = OntMetrics::default();
+        parse_gpon_counters(r#"var opticInfos = new Array(null);"#, &mut without);
+        assert_eq!(without.fec_corrected, None);
+        assert_eq!(without.bip_errors, None);
+    }
+
+    #[test]
+    fn test_parse_metrics_eg8145v5() {
+        let html = r#"
+        var opticInfos = new Array(new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","2.50","-25.00","\x202\x2e12","\x2d19\x2e87","3310","52","12","\x2d\x2d","\x2d\x2d","HUAWEI\x20\x20\x20\x20\x20\x20\x20\x20\x20","2103R1234567AB\x20","230114","1310","1490","20","0","1","0"),null);
+        "#;
+
+        let metrics = parse_ont_metrics(html, &OpticalOptions::default()).unwrap();
+
+        assert_eq!(metrics.tx_power, 2.12);
+        assert_eq!(metrics.rx_power, -19.87);
+        assert_eq!(metrics.tx_power_nominal, Some(2.5));
+        assert_eq!(metrics.rx_power_nominal, Some(-25.0));
+        assert_eq!(metrics.optical_modules[0].tx_power_nominal, Some(2.5));
+        assert_eq!(metrics.optical_modules[0].rx_power_nominal, Some(-25.0));
+        assert_eq!(metrics.voltage, 3310.0);
+        assert_eq!(metrics.temperature, 52.0);
+        assert_eq!(metrics.bias_current, 12.0);
+    }
+
+    #[test]
+    fn test_parse_optical_field_count() {
+        let count = |html: &str| parse_ont_metrics(html, &OpticalOptions::default()).unwrap().optical_field_count;
+
+        let short = r#"new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","2.33","-24.09","3364","47","10")"#;
+        assert_eq!(count(short), Some(7));
+        // Quoted commas don't add fields
+        let comma_decimals = r#"new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","2,33","-24,09","3364","47","10","--")"#;
+        assert_eq!(count(comma_decimals), Some(8));
+
+        // Nothing to count without a stOpticInfo call
+        assert_eq!(count("var opticInfos = new Array(null);"), None);
+    }
+
+    #[test]
+    fn test_parse_nominal_power() {
+        let nominal = |html: &str| {
+            let metrics = parse_ont_metrics(html, &OpticalOptions::default()).unwrap();
+            (metrics.tx_power_nominal, metrics.rx_power_nominal)
+        };
+
+        let reported = r#"new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","2.50","-25.00","2.12","-19.87","3310","52","12","--","--","HUAWEI","2103R1234567AB","230114","1310","1490","20","0","1","0")"#;
+        assert_eq!(nominal(reported), (Some(2.5), Some(-25.0)));
+
+        // An unparsable nominal value doesn't fail the scrape
+        let placeholder = r#"new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","--","-25.00","2.12","-19.87","3310","52","12","--","--","HUAWEI","2103R1234567AB","230114","1310","1490","20","0","1","0")"#;
+        assert_eq!(nominal(placeholder), (None, Some(-25.0)));
+
+        // The default layout has no nominal fields
+        let absent = r#"new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","2.33","-24.09","3364","47","10")"#;
+        assert_eq!(nominal(absent), (None, None));
+    }
+
+    #[test]
+    fn test_parse_metrics_scaled_units() {
+        let html = r#"
+        var opticInfos = new Array(new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","2.33","-24.09","33640","47","5000"),null);
+        "#;
+        let options = OpticalOptions {
+            voltage_scale: 0.1,
+            bias_scale: 0.002,
+            ..Default::default()
+        };
+
+        let metrics = parse_ont_metrics(html, &options).unwrap();
+
+        assert_eq!(metrics.voltage, 3364.0);
+        assert_eq!(metrics.bias_current, 10.0);
+    }
+
+    #[test]
+    fn test_parse_metrics_raw_power() {
+        let html = r#"
+        var opticInfos = new Array(new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","1165","-12045","3364","47","10"),null);
+        "#;
+        let options = OpticalOptions {
+            power_scale: 0.002,
+            ..Default::default()
+        };
+
+        let metrics = parse_ont_metrics(html, &options).unwrap();
+
+        assert!((metrics.tx_power - 2.33).abs() < 1e-9);
+        assert!((metrics.rx_power - -24.09).abs() < 1e-9);
+        assert_eq!(metrics.voltage, 3364.0);
+    }
+
+    #[test]
+    fn test_parse_metrics_index_overrides() {
+        // Measured values after two unrelated fields, on a short (non-EG8145V5) array
+        let html = r#"
+        var opticInfos = new Array(new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","x","y","2.33","-24.09","3364","47","10"),null);
+        "#;
+        let options = OpticalOptions {
+            index_overrides: OpticalIndexOverrides {
+                tx: Some(4),
+                rx: Some(5),
+                voltage: Some(6),
+                temperature: Some(7),
+                bias: Some(8),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let metrics = parse_ont_metrics(html, &options).unwrap();
+        assert_eq!(metrics.tx_power, 2.33);
+        assert_eq!(metrics.rx_power, -24.09);
+        assert_eq!(metrics.bias_current, 10.0);
+
+        // Without the overrides the default layout reads the placeholders
+        assert!(parse_ont_metrics(html, &OpticalOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_parse_metrics_comma_decimals() {
+        let html = r#"
+        var opticInfos = new Array(new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","\x202,33","\x2d24,09","3364","47,5","10,2","\x2d\x2d","\x2d\x2d"),null);
+        "#;
+
+        let metrics = parse_ont_metrics(html, &OpticalOptions::default()).unwrap();
+
+        assert_eq!(metrics.tx_power, 2.33);
+        assert_eq!(metrics.rx_power, -24.09);
+        assert_eq!(metrics.voltage, 3364.0);
+        assert_eq!(metrics.temperature, 47.5);
+        assert_eq!(metrics.bias_current, 10.2);
+    }
+
+    #[test]
+    fn test_parse_rx_sensitivity() {
+        let html = r#"
+        var opticInfos = new Array(new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","2.33","-24.09","3364","47","10"),null);
+        var RxSensitivity = "-27";
+        "#;
+
+        let metrics = parse_ont_metrics(html, &OpticalOptions::default()).unwrap();
+        assert_eq!(metrics.rx_sensitivity, Some(-27.0));
+
+        // Most firmware doesn't render it
+        let without = r#"var opticInfos = new Array(new stOpticInfo("InternetGatewayDevice.X_HW_DEBUG.AMP.Optic","ok","2.33","-24.09","3364","47","10"),null);"#;
+        assert_eq!(parse_ont_metrics(without, &OpticalOptions::default()).unwrap().rx_sensitivity, None);
+    }
+
     #[test]
     fn test_parse_device_info() {
         let html = r#"
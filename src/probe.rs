@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Keeps on-demand probes from hammering the ONT: at most one probe per target in
+/// flight, and a minimum interval between the start of consecutive probes.
+pub struct ProbeGuard {
+    min_interval: Duration,
+    targets: Mutex<HashMap<String, TargetState>>,
+}
+
+#[derive(Default)]
+struct TargetState {
+    in_flight: bool,
+    last_start: Option<Instant>,
+}
+
+pub enum ProbeRejection {
+    InFlight,
+    TooSoon(Duration),
+}
+
+impl ProbeGuard {
+    pub fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            targets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn try_acquire(&self, target: &str) -> Result<ProbePermit<'_>, ProbeRejection> {
+        let mut targets = self.targets.lock().unwrap();
+        let state = targets.entry(target.to_string()).or_default();
+
+        if state.in_flight {
+            return Err(ProbeRejection::InFlight);
+        }
+        if let Some(last) = state.last_start {
+            let elapsed = last.elapsed();
+            if elapsed < self.min_interval {
+                return Err(ProbeRejection::TooSoon(self.min_interval - elapsed));
+            }
+        }
+
+        state.in_flight = true;
+        state.last_start = Some(Instant::now());
+        Ok(ProbePermit {
+            guard: self,
+            target: target.to_string(),
+        })
+    }
+}
+
+/// Releases the in-flight slot for a target when dropped
+pub struct ProbePermit<'a> {
+    guard: &'a ProbeGuard,
+    target: String,
+}
+
+impl Drop for ProbePermit<'_> {
+    fn drop(&mut self) {
+        if let Some(state) = self.guard.targets.lock().unwrap().get_mut(&self.target) {
+            state.in_flight = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_guard_rejects_concurrent_and_rapid_probes() {
+        let guard = ProbeGuard::new(Duration::from_secs(60));
+
+        let permit = guard.try_acquire("http://a").ok().unwrap();
+        assert!(matches!(guard.try_acquire("http://a"), Err(ProbeRejection::InFlight)));
+        assert!(guard.try_acquire("http://b").is_ok());
+
+        drop(permit);
+        assert!(matches!(guard.try_acquire("http://a"), Err(ProbeRejection::TooSoon(_))));
+    }
+}